@@ -0,0 +1,243 @@
+//! Cache bundles, for sharing a range of cached logs without everyone having
+//! to download them again.
+//!
+//! A bundle is a zstd compressed tar archive. The first file is
+//! `manifest.json`, which lists every other file in the archive along with its
+//! checksum and when it was last used.
+
+use crate::cache;
+use crate::github::{Conclusion, Jobs, WorkflowRuns};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    range: String,
+    entries: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    /// The path relative to the cache directory, e.g. `jobs/123.json`.
+    path: String,
+    /// The length and hash of the file. See [`cache::checksum`].
+    checksum: String,
+    /// When the entry was last used, in seconds since the unix epoch.
+    modified: u64,
+}
+
+/// Package everything cached for the runs in `range` into a bundle.
+pub fn export(range: &str, output: &Path) -> io::Result<()> {
+    let runs_path = format!("{}/{range}.json", cache::RUNS_DIR);
+    let Some(runs) = cache::read_untouched(Path::new(&runs_path))? else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no cached run list for {range}"),
+        ));
+    };
+    let runs: Vec<WorkflowRuns> = serde_json::from_slice(&runs).map_err(io::Error::other)?;
+
+    let mut paths = vec![runs_path];
+    for run in runs.iter().flat_map(|runs| &runs.workflow_runs) {
+        let id = run.id;
+        paths.push(format!("{}/{id}.zip", cache::RUN_LOGS_DIR));
+        let jobs_path = format!("{}/{id}.json", cache::JOBS_DIR);
+        let Some(jobs) = cache::read_untouched(Path::new(&jobs_path))? else {
+            continue;
+        };
+        let jobs: Jobs = serde_json::from_slice(&jobs).map_err(io::Error::other)?;
+        paths.push(jobs_path);
+        for job in jobs.jobs {
+            if job.conclusion == Conclusion::Failure {
+                paths.push(format!("{}/{}.txt", cache::JOB_LOGS_DIR, job.id));
+                // The failed step, if it was taken from the run's logs.
+                let extract_dir = format!("{}/{id}", cache::RUN_LOGS_DIR);
+                paths.push(format!("{extract_dir}/{}.txt", job.id));
+                paths.push(format!("{extract_dir}/{}.processed.json", job.id));
+            }
+        }
+    }
+
+    let mut manifest = Manifest {
+        range: range.into(),
+        entries: Vec::new(),
+    };
+    // The manifest comes first, so every file is checked before any is
+    // written. They're read again to write them, one at a time, as a month
+    // of logs won't fit in memory.
+    for path in paths {
+        let path = Path::new(&path);
+        let Some(checksum) = cache::checksum_untouched(path)? else {
+            continue;
+        };
+        let modified = fs::metadata(path)?
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let relative = path.strip_prefix(cache::ROOT).map_err(io::Error::other)?;
+        manifest.entries.push(ManifestEntry {
+            path: relative.to_string_lossy().replace('\\', "/"),
+            checksum,
+            modified,
+        });
+    }
+
+    // Written to a temporary file first so a failed export doesn't leave a
+    // truncated bundle behind.
+    let mut tmp = output.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let result = write_bundle(&manifest, File::create(&tmp)?)
+        .and_then(|file| file.sync_all())
+        .and_then(|_| fs::rename(&tmp, output));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result?;
+    println!(
+        "exported {} entries to {}",
+        manifest.entries.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn write_bundle(manifest: &Manifest, file: File) -> io::Result<File> {
+    let mut tar = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+    let manifest_json = serde_json::to_vec_pretty(manifest).map_err(io::Error::other)?;
+    append(
+        &mut tar,
+        MANIFEST,
+        manifest_json.len() as u64,
+        &manifest_json[..],
+        0,
+    )?;
+    for entry in &manifest.entries {
+        let file = File::open(Path::new(cache::ROOT).join(&entry.path))?;
+        let len = file.metadata()?.len();
+        append(&mut tar, &entry.path, len, file.take(len), entry.modified)?;
+        println!("exported {}", entry.path);
+    }
+    tar.into_inner()?.finish()
+}
+
+fn append<W: io::Write>(
+    tar: &mut tar::Builder<W>,
+    path: &str,
+    len: u64,
+    data: impl Read,
+    modified: u64,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(len);
+    header.set_mode(0o644);
+    header.set_mtime(modified);
+    header.set_cksum();
+    tar.append_data(&mut header, path, data)
+}
+
+/// Merge a bundle into the local cache.
+///
+/// Every file is checked against the manifest before it's written. Local
+/// entries that are valid and have been used more recently than the bundled
+/// copy are left alone.
+pub fn import(input: &Path) -> io::Result<()> {
+    let decoder = zstd::Decoder::new(File::open(input)?)?;
+    let mut tar = tar::Archive::new(decoder);
+    let mut entries = tar.entries()?;
+
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let manifest: Manifest = match entries.next() {
+        Some(entry) => {
+            let mut entry = entry?;
+            if entry.path()?.as_ref() != Path::new(MANIFEST) {
+                return Err(invalid(format!("bundle doesn't start with {MANIFEST}")));
+            }
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            serde_json::from_slice(&data).map_err(|e| invalid(format!("bad manifest: {e}")))?
+        }
+        None => return Err(invalid("empty bundle".into())),
+    };
+    let mut expected: HashMap<&str, &ManifestEntry> = manifest
+        .entries
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect();
+
+    let (mut imported, mut kept, mut rejected) = (0, 0, 0);
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let Some(manifest_entry) = expected.remove(path.as_str()) else {
+            println!("rejected {path}: not in the manifest");
+            rejected += 1;
+            continue;
+        };
+        if !is_cache_path(Path::new(&path)) {
+            println!("rejected {path}: not a cache path");
+            rejected += 1;
+            continue;
+        }
+
+        let target = Path::new(cache::ROOT).join(&path);
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(manifest_entry.modified);
+        if cache::last_used(&target)?.is_some_and(|local| local >= modified) {
+            kept += 1;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Logs can be far too big to hold in memory, so they're written as
+        // they're read and only put in place once the checksum matches.
+        let mut writer = cache::Writer::new(&target)?;
+        io::copy(&mut entry, &mut writer)?;
+        if writer.checksum() != manifest_entry.checksum {
+            println!("rejected {path}: checksum mismatch");
+            rejected += 1;
+            continue;
+        }
+        writer.finish()?;
+        cache::set_last_used(&target, modified)?;
+        println!("imported {path}");
+        imported += 1;
+    }
+    for path in expected.keys() {
+        println!("missing {path}: in the manifest but not the bundle");
+    }
+    println!(
+        "imported {imported} entries from {} ({}), kept {kept} up to date local entries, rejected {rejected}",
+        input.display(),
+        manifest.range,
+    );
+    Ok(())
+}
+
+/// Bundles may only write to the cache directories, or to the directory of a
+/// run's extracted steps, e.g. `logs/runs/123/456.txt`.
+fn is_cache_path(path: &Path) -> bool {
+    let dirs = [
+        cache::RUNS_DIR,
+        cache::JOBS_DIR,
+        cache::JOB_LOGS_DIR,
+        cache::RUN_LOGS_DIR,
+    ];
+    let full = Path::new(cache::ROOT).join(path);
+    let Some(parent) = full.parent() else {
+        return false;
+    };
+    let is_run_dir = parent.parent() == Some(Path::new(cache::RUN_LOGS_DIR))
+        && parent
+            .file_name()
+            .and_then(|id| id.to_str())
+            .is_some_and(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()));
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+        && (is_run_dir || dirs.iter().any(|dir| parent == Path::new(dir)))
+}
@@ -0,0 +1,547 @@
+//! The on-disk cache of everything downloaded from GitHub.
+//!
+//! Entries are plain files (or, for extracted run logs, directories). The
+//! modification time of an entry is bumped whenever it's read from so it can
+//! double as a "last used" time when pruning the cache.
+//!
+//! Writes go to a temporary file which is synced and then renamed into place
+//! so an interrupted run can't leave a half written entry behind. Each entry
+//! also gets a `.sum` file next to it holding its length and checksum, which
+//! is checked whenever the entry is read.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub const ROOT: &str = "cache";
+pub const RUNS_DIR: &str = "cache/runs";
+pub const JOBS_DIR: &str = "cache/jobs";
+pub const RUN_LOGS_DIR: &str = "cache/logs/runs";
+pub const JOB_LOGS_DIR: &str = "cache/logs/jobs";
+
+const SUM_EXT: &str = "sum";
+const TMP_EXT: &str = "tmp";
+
+/// Reads a cache entry and marks it as recently used.
+///
+/// Returns `None` if the entry doesn't exist or is corrupt.
+pub fn read(path: &str) -> io::Result<Option<Vec<u8>>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if let Err(reason) = check(Path::new(path), &data) {
+        eprintln!("discarding corrupt cache entry {path}: {reason}");
+        return Ok(None);
+    }
+    touch(path);
+    Ok(Some(data))
+}
+
+/// Reads a cache entry as text and marks it as recently used.
+///
+/// Returns `None` if the entry doesn't exist or is corrupt.
+pub fn read_to_string(path: &str) -> io::Result<Option<String>> {
+    match read(path)? {
+        Some(data) => String::from_utf8(data)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        None => Ok(None),
+    }
+}
+
+/// Opens a cache entry to be read a piece at a time and marks it as recently used.
+///
+/// This is for entries too large to hold in memory. The checksum is checked
+/// before it's returned, which means reading the entry twice.
+///
+/// Returns `None` if the entry doesn't exist or is corrupt.
+pub fn open(path: &str) -> io::Result<Option<BufReader<File>>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // Unlike `check`, entries without a `.sum` aren't parsed. Large entries
+    // are logs, not JSON.
+    if let Ok(sum) = fs::read_to_string(with_suffix(Path::new(path), SUM_EXT)) {
+        let mut hasher = Hasher::default();
+        io::copy(&mut file, &mut hasher)?;
+        if let Err(reason) = compare(&sum, &hasher.sum()) {
+            eprintln!("discarding corrupt cache entry {path}: {reason}");
+            return Ok(None);
+        }
+        file = File::open(path)?;
+    }
+    touch(path);
+    Ok(Some(BufReader::new(file)))
+}
+
+/// Atomically writes a cache entry along with its checksum.
+pub fn write(path: impl AsRef<Path>, data: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut writer = Writer::new(path.as_ref())?;
+    writer.write_all(data.as_ref())?;
+    writer.finish()
+}
+
+/// Writes a cache entry a piece at a time, for entries too large to hold in memory.
+///
+/// Nothing is visible in the cache until [`Writer::finish`] is called.
+pub struct Writer {
+    path: PathBuf,
+    tmp: PathBuf,
+    file: File,
+    hasher: Hasher,
+    finished: bool,
+}
+
+impl Writer {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let tmp = with_suffix(path, TMP_EXT);
+        Ok(Self {
+            path: path.into(),
+            file: File::create(&tmp)?,
+            tmp,
+            hasher: Hasher::default(),
+            finished: false,
+        })
+    }
+
+    /// The checksum of what's been written so far. See [`checksum`].
+    pub fn checksum(&self) -> String {
+        self.hasher.sum()
+    }
+
+    /// Syncs the data and moves it into place along with its checksum.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        // The sum is written first. If we're interrupted before the data is
+        // renamed into place then the entry is just missing.
+        let sum = format!("{}\n", self.hasher.sum());
+        write_atomic(&with_suffix(&self.path, SUM_EXT), sum.as_bytes())?;
+        fs::rename(&self.tmp, &self.path)?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.hasher.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = with_suffix(path, TMP_EXT);
+    let mut file = File::create(&tmp)?;
+    let result = file
+        .write_all(data)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Appends an extension, e.g. `123.json` becomes `123.json.sum`.
+fn with_suffix(path: &Path, ext: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(ext);
+    PathBuf::from(path)
+}
+
+/// Checks an entry's data against its `.sum` file.
+///
+/// Entries written before checksums were added don't have one. JSON entries
+/// are checked by parsing them instead, anything else is assumed to be fine.
+fn check(path: &Path, data: &[u8]) -> Result<(), String> {
+    match fs::read_to_string(with_suffix(path, SUM_EXT)) {
+        Ok(sum) => compare(&sum, &checksum(data)),
+        Err(_) if path.extension().is_some_and(|e| e == "json") => {
+            serde_json::from_slice::<serde::de::IgnoredAny>(data)
+                .map(|_| ())
+                .map_err(|e| format!("invalid json: {e}"))
+        }
+        Err(_) => Ok(()),
+    }
+}
+
+/// Compares the contents of a `.sum` file with an entry's actual checksum.
+fn compare(sum: &str, actual: &str) -> Result<(), String> {
+    let expected = sum.trim();
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!("expected `{expected}`, found `{actual}`"))
+    }
+}
+
+/// Removes an entry along with its `.sum` file.
+fn remove(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    match fs::remove_file(with_suffix(path, SUM_EXT)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The length and hash of an entry's data, as stored in its `.sum` file.
+pub fn checksum(data: &[u8]) -> String {
+    let mut hasher = Hasher::default();
+    hasher.update(data);
+    hasher.sum()
+}
+
+/// The same hash as used for checksums, for anything else that needs one.
+pub fn hash(data: &[u8]) -> u64 {
+    let mut hasher = Hasher::default();
+    hasher.update(data);
+    hasher.hash
+}
+
+/// Reads a cache entry without marking it as used.
+///
+/// Returns `None` if the entry doesn't exist or is corrupt.
+pub fn read_untouched(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(check(path, &data).is_ok().then_some(data))
+}
+
+/// Works out the checksum of a cache entry a piece at a time, without marking
+/// it as used. See [`checksum`].
+///
+/// Returns `None` if the entry doesn't exist or is corrupt.
+pub fn checksum_untouched(path: &Path) -> io::Result<Option<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut hasher = Hasher::default();
+    io::copy(&mut BufReader::new(file), &mut hasher)?;
+    let sum = hasher.sum();
+    match fs::read_to_string(with_suffix(path, SUM_EXT)) {
+        Ok(expected) => Ok(compare(&expected, &sum).is_ok().then_some(sum)),
+        // Entries without a `.sum` are only checked if they're JSON, which
+        // is small enough to read.
+        Err(_) if path.extension().is_some_and(|e| e == "json") => {
+            Ok(read_untouched(path)?.map(|_| sum))
+        }
+        Err(_) => Ok(Some(sum)),
+    }
+}
+
+/// When a valid entry was last written or used.
+///
+/// Returns `None` if the entry doesn't exist or is corrupt.
+pub fn last_used(path: &Path) -> io::Result<Option<SystemTime>> {
+    if checksum_untouched(path)?.is_none() {
+        return Ok(None);
+    }
+    fs::metadata(path)?.modified().map(Some)
+}
+
+/// Sets when an entry was last used.
+pub fn set_last_used(path: &Path, time: SystemTime) -> io::Result<()> {
+    File::options().write(true).open(path)?.set_modified(time)
+}
+
+/// Works out the checksum of data written to it a piece at a time.
+///
+/// The hash is 64-bit FNV-1a. Good enough to spot truncation or garbage.
+struct Hasher {
+    len: u64,
+    hash: u64,
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            hash: 0xcbf29ce484222325,
+        }
+    }
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        for &b in data {
+            self.hash ^= u64::from(b);
+            self.hash = self.hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// The length and hash, as stored in a `.sum` file.
+    fn sum(&self) -> String {
+        format!("{} {:016x}", self.len, self.hash)
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Check every entry in the cache, printing any that are corrupt.
+///
+/// Leftover temporary files from interrupted writes are also reported.
+/// If `delete` is true then corrupt entries are removed.
+pub fn verify(delete: bool) -> io::Result<()> {
+    let mut counts = Counts::default();
+    for dir in [RUNS_DIR, JOBS_DIR, JOB_LOGS_DIR, RUN_LOGS_DIR] {
+        verify_dir(Path::new(dir), delete, &mut counts)?;
+    }
+    let Counts { checked, corrupt } = counts;
+    println!("checked {checked} entries, {corrupt} corrupt");
+    Ok(())
+}
+
+#[derive(Default)]
+struct Counts {
+    checked: usize,
+    corrupt: usize,
+}
+
+/// Checks the entries in a directory and its subdirectories, e.g. the steps
+/// extracted from a run's logs.
+fn verify_dir(dir: &Path, delete: bool, counts: &mut Counts) -> io::Result<()> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in read_dir {
+        let path = entry?.path();
+        let reason = if path.extension().is_some_and(|e| e == TMP_EXT) {
+            Some("interrupted write".to_string())
+        } else if path.extension().is_some_and(|e| e == SUM_EXT) {
+            // The entry may have already been removed along with its sum.
+            if fs::exists(&path)? && !fs::exists(path.with_extension(""))? {
+                Some("checksum without an entry".to_string())
+            } else {
+                None
+            }
+        } else if path.is_dir() {
+            verify_dir(&path, delete, counts)?;
+            None
+        } else {
+            counts.checked += 1;
+            check(&path, &fs::read(&path)?).err()
+        };
+        if let Some(reason) = reason {
+            counts.corrupt += 1;
+            if delete {
+                remove(&path)?;
+                println!("removed {}: {reason}", path.display());
+            } else {
+                println!("corrupt {}: {reason}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Marks an entry as recently used.
+///
+/// This is best effort. If it fails then the entry will just be pruned sooner.
+pub fn touch(path: &str) {
+    if let Ok(file) = File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+pub struct GcOptions {
+    /// Remove entries that haven't been used for this long.
+    pub older_than: Option<Duration>,
+    /// Evict the least recently used entries until the cache is at most this many bytes.
+    pub max_size: Option<u64>,
+    /// Also allow the run lists to be removed.
+    pub include_runs: bool,
+    /// Only print what would be removed.
+    pub dry_run: bool,
+}
+
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    used: SystemTime,
+}
+
+/// Prune the cache according to the given options, printing everything removed.
+pub fn gc(options: &GcOptions) -> io::Result<()> {
+    let mut kept_size = 0;
+    let mut entries = Vec::new();
+    for dir in [JOBS_DIR, JOB_LOGS_DIR, RUN_LOGS_DIR, RUNS_DIR] {
+        let dir_entries = match collect_entries(Path::new(dir)) {
+            Ok(e) => e,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if dir == RUNS_DIR && !options.include_runs {
+            kept_size += dir_entries.iter().map(|e| e.size).sum::<u64>();
+        } else {
+            entries.extend(dir_entries);
+        }
+    }
+    // Oldest first.
+    entries.sort_by_key(|e| e.used);
+
+    let mut to_remove = Vec::new();
+    if let Some(older_than) = options.older_than {
+        let cutoff = SystemTime::now()
+            .checked_sub(older_than)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let count = entries.iter().take_while(|e| e.used < cutoff).count();
+        to_remove.extend(entries.drain(..count));
+    }
+    if let Some(max_size) = options.max_size {
+        let mut size = kept_size + entries.iter().map(|e| e.size).sum::<u64>();
+        let count = entries
+            .iter()
+            .take_while(|e| {
+                let over = size > max_size;
+                size -= e.size;
+                over
+            })
+            .count();
+        to_remove.extend(entries.drain(..count));
+    }
+
+    let verb = if options.dry_run {
+        "would remove"
+    } else {
+        "removed"
+    };
+    let mut removed_size = 0;
+    for entry in &to_remove {
+        if !options.dry_run {
+            remove(&entry.path)?;
+        }
+        removed_size += entry.size;
+        println!(
+            "{verb} {} ({})",
+            entry.path.display(),
+            human_size(entry.size)
+        );
+    }
+    println!(
+        "{verb} {} entries ({}), {} remaining",
+        to_remove.len(),
+        human_size(removed_size),
+        human_size(kept_size + entries.iter().map(|e| e.size).sum::<u64>()),
+    );
+    Ok(())
+}
+
+fn collect_entries(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        // Checksums are removed along with their entry.
+        if dir_entry.path().extension().is_some_and(|e| e == SUM_EXT) {
+            continue;
+        }
+        let metadata = dir_entry.metadata()?;
+        let (size, used) = if metadata.is_dir() {
+            dir_usage(&dir_entry.path())?
+        } else {
+            (metadata.len(), metadata.modified()?)
+        };
+        entries.push(Entry {
+            path: dir_entry.path(),
+            size,
+            used,
+        });
+    }
+    Ok(entries)
+}
+
+/// The size of everything in a directory, and when any of it was last used.
+///
+/// Using a file doesn't change its directory's modification time, so a
+/// directory is as recently used as the newest file in it.
+fn dir_usage(dir: &Path) -> io::Result<(u64, SystemTime)> {
+    let mut size = 0;
+    let mut used = fs::metadata(dir)?.modified()?;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let (entry_size, entry_used) = if metadata.is_dir() {
+            dir_usage(&entry.path())?
+        } else {
+            (metadata.len(), metadata.modified()?)
+        };
+        size += entry_size;
+        used = used.max(entry_used);
+    }
+    Ok((size, used))
+}
+
+/// Parses a size such as `500M` or `2G`. A plain number is in bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => s.split_at(pos),
+        None => (s, ""),
+    };
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size unit `{unit}`")),
+    };
+    let num: u64 = num
+        .parse()
+        .map_err(|e| format!("invalid size `{s}`: {e}"))?;
+    num.checked_mul(multiplier)
+        .ok_or_else(|| format!("size `{s}` is too large"))
+}
+
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
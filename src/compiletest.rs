@@ -0,0 +1,210 @@
+//! Reading compiletest failures, which is how most of rustc's tests fail.
+//!
+//! compiletest runs its tests under libtest, with names like
+//! `[ui] tests/ui/foo.rs#rev`. The output of a failed test says what went
+//! wrong and which command was run:
+//!
+//! ```text
+//! ---- [ui] tests/ui/foo.rs#rev stdout ----
+//! error in revision `rev`: test compilation failed although it shouldn't!
+//! status: exit status: 1
+//! command: "/checkout/obj/build/x86_64-unknown-linux-gnu/stage1/bin/rustc" "/checkout/tests/ui/foo.rs" ...
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompiletestFailure {
+    /// The mode the test ran in, e.g. `ui` or `assembly`.
+    pub mode: String,
+    /// The test suite, which is the directory under `tests`, e.g. `ui-fulldeps`.
+    pub suite: Option<String>,
+    /// The path to the test, as written in its name.
+    pub path: String,
+    pub revision: Option<String>,
+    pub kind: Option<FailureKind>,
+    /// The command that failed.
+    pub command: Option<String>,
+    /// How the output differed from what was expected, for UI tests.
+    #[serde(default)]
+    pub diffs: Vec<Diff>,
+}
+
+/// A diff of the output compiletest expected against what it got:
+///
+/// ```text
+/// diff of stderr:
+///
+/// -\terror: expected
+/// +\terror: actual
+///
+/// The actual stderr differed from the expected stderr
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Diff {
+    /// Which output it is, e.g. `stderr` or `fixed`.
+    pub output: String,
+    /// The diff as compiletest printed it.
+    pub diff: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Change {
+    Removed,
+    Added,
+    Unchanged,
+    /// The `@@ -1,2 +1,2 @@` line at the start of each part of the diff.
+    Hunk,
+}
+
+impl Diff {
+    fn new(output: String, mut lines: &[&str]) -> Self {
+        while let [first, rest @ ..] = lines
+            && first.trim().is_empty()
+        {
+            lines = rest;
+        }
+        while let [rest @ .., last] = lines
+            && last.trim().is_empty()
+        {
+            lines = rest;
+        }
+        Self {
+            output,
+            diff: lines.join("\n"),
+        }
+    }
+
+    /// The lines of the diff with what each one is.
+    pub fn lines(&self) -> impl Iterator<Item = (Change, &str)> {
+        self.diff.lines().map(|line| {
+            let change = if line.starts_with("-\t") {
+                Change::Removed
+            } else if line.starts_with("+\t") {
+                Change::Added
+            } else if line.starts_with("@@ ") {
+                Change::Hunk
+            } else {
+                Change::Unchanged
+            };
+            (change, line)
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    CompileFailed,
+    UnexpectedOutput,
+    RunFailed,
+    AuxBuildFailed,
+}
+
+impl FailureKind {
+    /// Part of the error compiletest gives for each kind of failure.
+    const ERRORS: [(&str, FailureKind); 8] = [
+        ("auxiliary build of ", FailureKind::AuxBuildFailed),
+        ("test compilation failed", FailureKind::CompileFailed),
+        ("compilation failed!", FailureKind::CompileFailed),
+        ("test run failed!", FailureKind::RunFailed),
+        ("rmake recipe failed to complete", FailureKind::RunFailed),
+        ("The actual std", FailureKind::UnexpectedOutput),
+        // E.g. `1 errors occurred comparing output.`
+        (
+            "errors occurred comparing output",
+            FailureKind::UnexpectedOutput,
+        ),
+        // E.g. `1 unexpected errors found, 0 expected errors not found`
+        ("unexpected errors found", FailureKind::UnexpectedOutput),
+    ];
+
+    fn of(error: &str) -> Option<Self> {
+        Self::ERRORS
+            .iter()
+            .find(|(part, _)| error.contains(part))
+            .map(|&(_, kind)| kind)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::CompileFailed => "compile failed",
+            Self::UnexpectedOutput => "unexpected output",
+            Self::RunFailed => "run failed",
+            Self::AuxBuildFailed => "aux build failed",
+        }
+    }
+}
+
+/// Parses the failure of a test, if it's a compiletest test.
+pub fn parse(name: &str, stdout: &[String]) -> Option<CompiletestFailure> {
+    let (mode, test) = name.strip_prefix('[')?.split_once("] ")?;
+    let (path, mut revision) = match test.split_once('#') {
+        Some((path, revision)) => (path, Some(revision.to_string())),
+        None => (test, None),
+    };
+    let mut kind = None;
+    let mut command = None;
+    let mut diffs = Vec::new();
+    // The output and lines of the diff being read, if any.
+    let mut diff: Option<(String, Vec<&str>)> = None;
+    for line in stdout {
+        if let Some((output, mut lines)) = diff.take() {
+            if line.starts_with("The actual ") || line.starts_with("diff of ") {
+                diffs.push(Diff::new(output, &lines));
+            } else {
+                lines.push(line);
+                diff = Some((output, lines));
+                continue;
+            }
+        }
+        if let Some(output) = line
+            .strip_prefix("diff of ")
+            .and_then(|s| s.strip_suffix(':'))
+        {
+            diff = Some((output.into(), Vec::new()));
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("command: ") {
+            command.get_or_insert_with(|| rest.to_string());
+            continue;
+        }
+        // Errors either start with `error: ` or say which revision they're
+        // from. Some of the lines saying what went wrong aren't errors at all.
+        let error = if let Some(rest) = line.strip_prefix("error in revision `")
+            && let Some((rev, error)) = rest.split_once("`: ")
+        {
+            revision.get_or_insert_with(|| rev.to_string());
+            error
+        } else {
+            line.strip_prefix("error: ").unwrap_or(line)
+        };
+        if kind.is_none() {
+            kind = FailureKind::of(error);
+        }
+    }
+    if let Some((output, lines)) = diff {
+        diffs.push(Diff::new(output, &lines));
+    }
+    Some(CompiletestFailure {
+        mode: mode.into(),
+        suite: suite(path),
+        path: path.into(),
+        revision,
+        kind,
+        command,
+        diffs,
+    })
+}
+
+/// The directory under `tests` that the test is in. Tests on Windows are
+/// named with backslashes.
+fn suite(path: &str) -> Option<String> {
+    let parts: Vec<&str> = path.split(['/', '\\']).collect();
+    let tests = parts.iter().position(|&p| p == "tests")?;
+    // The last part is the test itself.
+    parts
+        .get(tests + 1..parts.len() - 1)?
+        .first()
+        .map(|s| s.to_string())
+}
@@ -0,0 +1,249 @@
+//! Shortening what's too long to read in a short log: long command lines,
+//! such as compiletest's `command: PATH="..." "rustc" ...`, dumps of the
+//! environment and huge values like `PATH`.
+
+use crate::log::{Line, LineKind};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// Lines longer than this, in characters, are shortened.
+const MAX_LINE: usize = 500;
+/// Words longer than this, in characters, are left out of a line's summary.
+const MAX_WORD: usize = 100;
+/// The most characters of a line's summary.
+const MAX_SUMMARY: usize = 200;
+/// The fewest variables that make a dump of the environment.
+const MIN_VARIABLES: usize = 5;
+
+/// Lines of a log that are shown shortened.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Elided {
+    /// Which lines, counting from 0.
+    pub lines: Range<usize>,
+    /// What's shown instead of them.
+    pub summary: String,
+}
+
+/// Finds what should be shortened in the lines.
+pub fn find(lines: &[Line]) -> Vec<Elided> {
+    let mut elided = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some(found) = environment(&lines[i..]) {
+            let count = found.lines.len();
+            elided.push(Elided {
+                lines: found.lines.start + i..found.lines.end + i,
+                ..found
+            });
+            i += count;
+            continue;
+        }
+        let line = &lines[i];
+        if line.kind == LineKind::Text && line.text.chars().count() > MAX_LINE {
+            elided.push(Elided {
+                lines: i..i + 1,
+                summary: summarize(&line.text),
+            });
+        }
+        i += 1;
+    }
+    elided
+}
+
+/// Finds a dump of the environment at the start of the lines, either from
+/// `env`, with a `NAME=value` line for each variable, or the `env:` that
+/// GitHub lists with a step:
+///
+/// ```text
+///   env:
+///     CI_JOB_NAME: x86_64-gnu
+///     TOOLSTATE_REPO: https://github.com/rust-lang-nursery/rust-toolstate
+/// ```
+fn environment(lines: &[Line]) -> Option<Elided> {
+    let first = lines.first()?;
+    let (count, elided) = if first.text.trim() == "env:" {
+        let width = indent(&first.text);
+        let count = count_while(&lines[1..], |t| {
+            indent(t) > width
+                && t.trim_start()
+                    .split_once(": ")
+                    .is_some_and(|(name, _)| is_name(name))
+        });
+        let summary = format!("{}env: ({count} variables)", &first.text[..width]);
+        (
+            count,
+            Elided {
+                lines: 0..count + 1,
+                summary,
+            },
+        )
+    } else {
+        let count = count_while(lines, |t| variable(t).is_some());
+        let summary = format!("({count} environment variables)");
+        (
+            count,
+            Elided {
+                lines: 0..count,
+                summary,
+            },
+        )
+    };
+    (first.kind == LineKind::Text && count >= MIN_VARIABLES).then_some(elided)
+}
+
+/// How many lines at the start are text for which `f` is true.
+fn count_while(lines: &[Line], f: impl Fn(&str) -> bool) -> usize {
+    lines
+        .iter()
+        .take_while(|l| l.kind == LineKind::Text && f(&l.text))
+        .count()
+}
+
+/// The width of the whitespace at the start of the text, in bytes.
+fn indent(text: &str) -> usize {
+    text.len() - text.trim_start().len()
+}
+
+/// The name and value of a `NAME=value` line or word.
+fn variable(text: &str) -> Option<(&str, &str)> {
+    let text = text.strip_prefix("export ").unwrap_or(text);
+    let (name, value) = text.split_once('=')?;
+    is_name(name).then_some((name, value))
+}
+
+fn is_name(name: &str) -> bool {
+    name.bytes().next().is_some_and(|b| !b.is_ascii_digit())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// The line with its long words, such as a `PATH`, left out and cut down to
+/// [`MAX_SUMMARY`] characters, with how long it was.
+fn summarize(text: &str) -> String {
+    let mut summary = String::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let start = indent(rest);
+        summary.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = word_end(rest);
+        summary.push_str(&shorten(&rest[..end]));
+        rest = &rest[end..];
+    }
+    if summary.chars().count() > MAX_SUMMARY {
+        summary = summary.chars().take(MAX_SUMMARY).collect();
+        summary.push('…');
+    }
+    format!("{summary} ({} characters)", text.chars().count())
+}
+
+/// Where the word at the start of the text ends. Whitespace in quotes is
+/// part of the word.
+fn word_end(text: &str) -> usize {
+    let mut quoted = false;
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            // Commands are printed with `{:?}`, so quotes in them are escaped.
+            '\\' if quoted => {
+                chars.next();
+            }
+            c if c.is_whitespace() && !quoted => return i,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Leaves out the value of a long word, keeping its name if it's a variable.
+fn shorten(word: &str) -> Cow<'_, str> {
+    if word.chars().count() <= MAX_WORD {
+        return word.into();
+    }
+    let (name, value) = match variable(word) {
+        Some((_, value)) => (&word[..word.len() - value.len()], value),
+        None => ("", word),
+    };
+    if value.starts_with('"') && value.ends_with('"') {
+        format!("{name}\"…\"").into()
+    } else {
+        format!("{name}…").into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::Log;
+
+    fn find_in(log: &str) -> Vec<(Range<usize>, String)> {
+        find(&Log::parse(log).lines)
+            .into_iter()
+            .map(|e| (e.lines, e.summary))
+            .collect()
+    }
+
+    #[test]
+    fn env_dump() {
+        let log = "\
++ env
+CI=true
+HOME=/home/runner
+PATH=/usr/bin:/bin
+export RUSTFLAGS=-Dwarnings
+TOOLSTATE_REPO=https://github.com/rust-lang-nursery/rust-toolstate
+done
+";
+        assert_eq!(
+            find_in(log),
+            [(1..6, "(5 environment variables)".to_string())]
+        );
+    }
+
+    #[test]
+    fn few_variables_are_kept() {
+        assert!(find_in("CI=true\nHOME=/home/runner\n1X=no\n").is_empty());
+    }
+
+    #[test]
+    fn github_env() {
+        let log = "\
+##[group]Run src/ci/scripts/run-build-from-ci.sh
+  env:
+    CI_JOB_NAME: x86_64-gnu
+    TOOLSTATE_REPO: https://github.com/rust-lang-nursery/rust-toolstate
+    CACHE_DOMAIN: ci-caches.rust-lang.org
+    SCCACHE_BUCKET: rust-lang-ci-sccache2
+    DOCKER_TOKEN: ***
+##[endgroup]
+";
+        assert_eq!(find_in(log), [(1..7, "  env: (5 variables)".to_string())]);
+    }
+
+    #[test]
+    fn long_command() {
+        let path = format!("PATH=\"{}\"", "/usr/local/bin:".repeat(40));
+        let line = format!("command: {path} \"rustc\" \"/checkout/tests/ui/foo.rs\"");
+        let found = find_in(&format!("before\n{line}\nafter\n"));
+        assert_eq!(
+            found,
+            [(
+                1..2,
+                format!(
+                    "command: PATH=\"…\" \"rustc\" \"/checkout/tests/ui/foo.rs\" ({} characters)",
+                    line.len()
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn summaries_are_short() {
+        let line = "word ".repeat(200);
+        let found = find_in(&line);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].1.starts_with(&"word ".repeat(40)));
+        assert!(found[0].1.ends_with("… (1000 characters)"));
+    }
+}
@@ -0,0 +1,576 @@
+//! Extracting the interesting parts of a job's log.
+//!
+//! What's extracted is driven by the [rules](crate::rules). Processing a log is
+//! slow so the results are cached next to the raw log. The cache is keyed by
+//! [`VERSION`], which must be bumped whenever a change here would produce
+//! different output, and by the rules in use.
+
+use crate::log::{Line, LineKind, Lines, Log};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Seek};
+use std::path::Path;
+
+use crate::cache;
+use crate::elide::{self, Elided};
+use crate::github::{Job, Step};
+use crate::infra::{self, Category};
+use crate::libtest::{self, TestFailure};
+use crate::linker::{self, LinkerFailure};
+use crate::native::{self, NativeFailure};
+use crate::network::{self, NetworkFailure};
+use crate::panic::{self, Panic};
+use crate::rules::{Confidence, ErrorLineRule, Report, Rules, ShortLogRule};
+use crate::steps;
+use crate::timing::{self, GroupTimer, GroupTiming, Timing};
+
+/// The version of the extraction logic.
+pub const VERSION: u32 = 28;
+
+/// The processed output for a job's log.
+#[derive(Serialize, Deserialize)]
+pub struct Processed {
+    /// The [`key`] of the extractor that produced this.
+    pub extractor: String,
+    /// The last group of the log, before the cleanup at the end of the job,
+    /// with timestamps and colours. At most [`MAX_LOG_LINES`] lines of it are
+    /// kept, after its first line.
+    pub log: String,
+    /// With timestamps and colours, like `log`.
+    pub short_log: String,
+    /// The lines of the short log that are too long to show in full.
+    pub elided: Vec<Elided>,
+    pub error: Option<ErrorSummary>,
+    /// Where the headline of the error is in the raw log, in bytes.
+    pub error_offset: Option<u64>,
+    /// The step the log was taken from, if known.
+    pub step: Option<StepInfo>,
+    /// How long each group took, before the log was trimmed.
+    pub groups: Vec<GroupTiming>,
+    pub timing: Option<Timing>,
+    /// The tests that failed, if the log has libtest output.
+    pub tests: Vec<TestFailure>,
+    /// The first panic in the short log.
+    pub panic: Option<Panic>,
+    /// Why linking failed, if it did.
+    pub linker: Option<LinkerFailure>,
+    /// Why a C or C++ build failed, if one did.
+    pub native: Option<NativeFailure>,
+    /// Set if the failure was the machine's fault rather than the code's.
+    pub infra: Option<Category>,
+    /// Set if the network failed.
+    pub network: Option<NetworkFailure>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StepInfo {
+    pub number: u64,
+    pub name: String,
+}
+
+impl From<&Step> for StepInfo {
+    fn from(step: &Step) -> Self {
+        Self {
+            number: step.number,
+            name: step.name.clone(),
+        }
+    }
+}
+
+/// Identifies the extraction logic. Cached results with a different key are stale.
+pub fn key(rules: &Rules) -> String {
+    format!("v{VERSION}-{:016x}", rules.hash())
+}
+
+/// Runs the full extraction pipeline over the log of a whole job.
+///
+/// If the failed step can be found then only its part of the log is used.
+/// Otherwise the log is read again from the start and all of it is used.
+pub fn process_job_log(
+    mut log: impl BufRead + Seek,
+    job: &Job,
+    rules: &Rules,
+) -> io::Result<Processed> {
+    if let Some(failed) = job.failed_step() {
+        let mut pipeline = Pipeline::new(rules);
+        let mut steps = steps::Assigner::new(&job.steps);
+        for line in Lines::new(&mut log) {
+            let mut line = line?;
+            steps.assign(&mut line);
+            if line.step == Some(failed.number) {
+                pipeline.push(line);
+            }
+        }
+        if !pipeline.is_empty() {
+            return Ok(pipeline.finish(job, Some(failed.into())));
+        }
+        log.rewind()?;
+    }
+    let mut pipeline = Pipeline::new(rules);
+    let mut steps = steps::Assigner::new(&job.steps);
+    for line in Lines::new(log) {
+        let mut line = line?;
+        steps.assign(&mut line);
+        pipeline.push(line);
+    }
+    Ok(pipeline.finish(job, None))
+}
+
+/// Runs the full extraction pipeline over the log of a single step of a job.
+pub fn process_step_log(
+    log: impl BufRead,
+    job: &Job,
+    step: &Step,
+    rules: &Rules,
+) -> io::Result<Processed> {
+    let mut pipeline = Pipeline::new(rules);
+    for line in Lines::new(log) {
+        pipeline.push(line?);
+    }
+    Ok(pipeline.finish(job, Some(step.into())))
+}
+
+/// The stages a log goes through, a line at a time.
+///
+/// Only a bounded number of lines are ever kept so logs of any size can be
+/// processed in constant memory.
+struct Pipeline<'a> {
+    rules: &'a Rules,
+    groups: GroupTimer,
+    tests: libtest::Parser,
+    trimmer: Trimmer<'a>,
+}
+
+impl<'a> Pipeline<'a> {
+    fn new(rules: &'a Rules) -> Self {
+        Self {
+            rules,
+            groups: GroupTimer::default(),
+            tests: libtest::Parser::default(),
+            trimmer: Trimmer::new(&rules.short_log),
+        }
+    }
+
+    fn push(&mut self, line: Line) {
+        self.groups.push(&line);
+        self.tests.push(&line);
+        self.trimmer.push(line);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.trimmer.lines == 0
+    }
+
+    fn finish(self, job: &Job, step: Option<StepInfo>) -> Processed {
+        let groups = self.groups.finish();
+        let segment = self.trimmer.finish();
+        let mut short_log = segment.short_log(&self.rules.short_log);
+        let log = segment.into_log();
+        // A C or C++ build failure that can be read is better than any rule.
+        // Outside of a native build it has to say what went wrong, as e.g. a
+        // `FAILED: ` line could be anything.
+        let group = log.lines.first().filter(|l| l.kind == LineKind::GroupStart);
+        let native = native::find(&log.lines).filter(|found| {
+            group.is_some_and(|g| native::is_native_group(g.message()))
+                || found.failure.is_explained()
+        });
+        // The group's header is kept, as the rule for the group would have.
+        let header = group.filter(|_| native.as_ref().is_some_and(|f| f.lines.start > 0));
+        if let Some(found) = &native {
+            short_log.lines = header
+                .into_iter()
+                .chain(&log.lines[found.lines.clone()])
+                .cloned()
+                .collect();
+        }
+        let panic = panic::find(short_log.lines.iter().map(|l| l.text.as_str()));
+        let linker = linker::find(short_log.lines.iter().map(|l| l.text.as_str()));
+        let tests = self.tests.finish();
+        let lines = &short_log.lines;
+        let error = if let Some(found) = &native {
+            let confidence = if found.failure.is_explained() {
+                Confidence::High
+            } else {
+                Confidence::Low
+            };
+            let headline = found.error_line - found.lines.start + usize::from(header.is_some());
+            Some(summary(lines, headline, headline, "native", confidence))
+        } else if let Some(found) = &linker {
+            Some(linker_summary(lines, found))
+        } else {
+            let compiletest = tests.iter().find(|t| t.compiletest.is_some());
+            compiletest
+                .and_then(|test| compiletest_summary(lines, &test.name))
+                .or_else(|| error_summary(&short_log, self.rules))
+        };
+        let error_line = error.as_ref().map(|(_, line)| *line);
+        let timing = job
+            .started_at
+            .parse()
+            .ok()
+            .and_then(|started| timing::timing(started, &log, error_line));
+        Processed {
+            extractor: key(self.rules),
+            log: log.styled().to_string(),
+            error_offset: error_line.map(|l| l.offset),
+            error: error.map(|(error, _)| error),
+            elided: elide::find(&short_log.lines),
+            short_log: short_log.styled().to_string(),
+            step,
+            groups,
+            timing,
+            tests,
+            panic,
+            linker: linker.map(|found| found.failure),
+            native: native.map(|found| found.failure),
+            infra: infra::find(&log.lines),
+            network: network::find(&log.lines),
+        }
+    }
+}
+
+/// The most lines of the trimmed log that are kept, and so the most any
+/// short log rule can use.
+const MAX_LOG_LINES: usize = 10_000;
+
+/// Cuts the log down to the part most likely to contain the failure: the
+/// last group before the cleanup at the end of the job.
+struct Trimmer<'a> {
+    rules: &'a [ShortLogRule],
+    /// The number of lines seen.
+    lines: usize,
+    /// The lines since the last group started.
+    current: Segment,
+    /// What `current` was when the cleanup started.
+    before_cleanup: Option<Segment>,
+}
+
+impl<'a> Trimmer<'a> {
+    fn new(rules: &'a [ShortLogRule]) -> Self {
+        Self {
+            rules,
+            lines: 0,
+            current: Segment::new(rules),
+            before_cleanup: None,
+        }
+    }
+
+    fn push(&mut self, line: Line) {
+        if line.text == "Post job cleanup." && self.lines > 0 {
+            self.before_cleanup = Some(self.current.clone());
+        }
+        if line.kind == LineKind::GroupStart {
+            self.current = Segment::new(self.rules);
+        }
+        self.lines += 1;
+        self.current.push(line, self.rules);
+    }
+
+    fn finish(self) -> Segment {
+        self.before_cleanup.unwrap_or(self.current)
+    }
+}
+
+/// A part of the log that might end up as the trimmed log, along with what's
+/// needed to make the short log for it.
+#[derive(Clone)]
+struct Segment {
+    /// The first line, which is the group's `##[group]` line if it's a group.
+    first: Option<Line>,
+    tail: Tail<MAX_LOG_LINES>,
+    /// Where each short log rule's `from` window starts.
+    windows: Vec<Window>,
+}
+
+impl Segment {
+    fn new(rules: &[ShortLogRule]) -> Self {
+        Self {
+            first: None,
+            tail: Tail::default(),
+            windows: vec![Window::NotFound; rules.len()],
+        }
+    }
+
+    fn push(&mut self, line: Line, rules: &[ShortLogRule]) {
+        let is_first = self.first.is_none();
+        for (window, rule) in self.windows.iter_mut().zip(rules) {
+            let matches = rule.from.as_ref().is_some_and(|p| p.matches(&line.text));
+            window.push(&line, matches, is_first);
+        }
+        if is_first {
+            self.first = Some(line.clone());
+        }
+        self.tail.push(line);
+    }
+
+    /// The group this segment is, if any.
+    fn group(&self) -> Option<&Line> {
+        self.first
+            .as_ref()
+            .filter(|l| l.kind == LineKind::GroupStart)
+    }
+
+    /// The trimmed log, which is the first line and as much of the rest as was kept.
+    fn into_log(self) -> Log {
+        let mut log = Log::default();
+        if self.tail.dropped > 0 {
+            log.lines.extend(self.first);
+        }
+        log.lines.extend(self.tail.lines);
+        log
+    }
+
+    /// Uses the first rule that applies. If none do then it's the whole log.
+    fn short_log(&self, rules: &[ShortLogRule]) -> Log {
+        let group = self.group();
+        for (rule, window) in rules.iter().zip(&self.windows) {
+            if let Some(pattern) = &rule.group
+                && !group.is_some_and(|g| pattern.matches(g.message()))
+            {
+                continue;
+            }
+            let mut short = Log::default();
+            if let Window::Found(tail) = window {
+                if rule.group_header {
+                    short.lines.extend(group.cloned());
+                }
+                short.lines.extend(tail.lines.iter().cloned());
+                return short;
+            }
+            if let Some(count) = rule.tail {
+                // The group line itself isn't part of the tail.
+                let rest = self.tail.total() - usize::from(group.is_some());
+                short.lines.extend(group.cloned());
+                short.lines.extend(self.tail.last(count.min(rest)).cloned());
+                return short;
+            }
+        }
+        self.clone().into_log()
+    }
+}
+
+/// Where a short log might start.
+#[derive(Clone)]
+enum Window {
+    NotFound,
+    /// The first match was the first line, which doesn't count.
+    AtStart,
+    /// The lines from the first match onwards.
+    Found(Tail<MAX_LOG_LINES>),
+}
+
+impl Window {
+    fn push(&mut self, line: &Line, matches: bool, is_first: bool) {
+        match self {
+            Self::NotFound if matches && is_first => *self = Self::AtStart,
+            Self::NotFound if matches => {
+                let mut tail = Tail::default();
+                tail.push(line.clone());
+                *self = Self::Found(tail);
+            }
+            Self::Found(tail) => tail.push(line.clone()),
+            Self::NotFound | Self::AtStart => {}
+        }
+    }
+}
+
+/// The last `N` lines pushed.
+#[derive(Clone, Default)]
+struct Tail<const N: usize> {
+    lines: VecDeque<Line>,
+    /// How many lines were pushed out of the front.
+    dropped: usize,
+}
+
+impl<const N: usize> Tail<N> {
+    fn push(&mut self, line: Line) {
+        if self.lines.len() == N {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+        self.lines.push_back(line);
+    }
+
+    /// The number of lines ever pushed.
+    fn total(&self) -> usize {
+        self.dropped + self.lines.len()
+    }
+
+    fn last(&self, count: usize) -> impl Iterator<Item = &Line> {
+        self.lines
+            .iter()
+            .skip(self.lines.len().saturating_sub(count))
+    }
+}
+
+/// Loads cached results, if they exist and are up to date.
+pub fn load(path: &str, rules: &Rules) -> io::Result<Option<Processed>> {
+    let Some(data) = cache::read(path)? else {
+        return Ok(None);
+    };
+    match serde_json::from_slice::<Processed>(&data) {
+        Ok(processed) if processed.extractor == key(rules) => Ok(Some(processed)),
+        // Either stale or from an older format, so it needs recomputing.
+        _ => Ok(None),
+    }
+}
+
+/// Caches the processed results.
+pub fn save(path: impl AsRef<Path>, processed: &Processed) -> io::Result<()> {
+    let json = serde_json::to_vec(processed).map_err(io::Error::other)?;
+    cache::write(path, json)
+}
+
+/// The most lines of the log kept as the context of an error.
+const MAX_CONTEXT_LINES: usize = 10;
+
+/// What the failure was, in a line, and the lines of the log that explain it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorSummary {
+    /// The line that best describes the failure.
+    pub headline: String,
+    /// The lines of the log around the headline, including it.
+    pub context: Vec<String>,
+    /// What found the headline: the name of an error line rule, or `native`,
+    /// `linker` or `compiletest` if it was read from a failure of that kind.
+    pub rule: String,
+    pub confidence: Confidence,
+}
+
+/// Summarizes the error with the line at `headline`. The context is from
+/// `start` to the next blank line, or workflow command, after the headline,
+/// but never more than [`MAX_CONTEXT_LINES`].
+fn summary<'a>(
+    lines: &'a [Line],
+    start: usize,
+    headline: usize,
+    rule: &str,
+    confidence: Confidence,
+) -> (ErrorSummary, &'a Line) {
+    // The start is left out if it's too far before the headline.
+    let start = start.max(headline.saturating_sub(MAX_CONTEXT_LINES - 1));
+    let end = lines[headline + 1..]
+        .iter()
+        .position(|l| l.text.trim().is_empty() || l.kind != LineKind::Text)
+        .map_or(lines.len(), |i| headline + 1 + i)
+        .min(start + MAX_CONTEXT_LINES)
+        .max(headline + 1);
+    let error = ErrorSummary {
+        headline: lines[headline].text.clone(),
+        context: lines[start..end].iter().map(|l| l.text.clone()).collect(),
+        rule: rule.into(),
+        confidence,
+    };
+    (error, &lines[headline])
+}
+
+/// Summarizes a linker failure with what the linker said, rather than
+/// rustc's line saying that it failed.
+fn linker_summary<'a>(lines: &'a [Line], found: &linker::Found) -> (ErrorSummary, &'a Line) {
+    let headline = found.error_line;
+    let text = lines[headline].text.trim();
+    if text.starts_with("error: linking with `") {
+        // What comes next is the linker's command line, which is no help.
+        return summary(lines, headline, headline, "linker", Confidence::Medium);
+    }
+    // GNU ld says which function the error is in on the line before.
+    let start = match headline.checked_sub(1) {
+        Some(before) if lines[before].text.contains(": in function `") => before,
+        _ => headline,
+    };
+    let (mut error, line) = summary(lines, start, headline, "linker", Confidence::High);
+    error.headline = text.strip_prefix("= note: ").unwrap_or(text).into();
+    (error, line)
+}
+
+/// Summarizes a compiletest test's failure with the compiler's first error,
+/// or the first difference in the output, rather than compiletest's line
+/// saying which of those it was.
+fn compiletest_summary<'a>(lines: &'a [Line], test: &str) -> Option<(ErrorSummary, &'a Line)> {
+    let start = lines
+        .iter()
+        .position(|l| libtest::section_name(&l.text) == Some(test))?
+        + 1;
+    let end = lines[start..]
+        .iter()
+        .position(|l| libtest::section_name(&l.text).is_some() || l.text == "failures:")
+        .map_or(lines.len(), |i| start + i);
+    // The first line from `from` that starts with any of the prefixes.
+    let find = |from: usize, prefixes: &[&str]| {
+        (from..end).find(|&i| prefixes.iter().any(|p| lines[i].text.starts_with(p)))
+    };
+    if let Some(stderr) = find(start, &["--- stderr "])
+        && let Some(error) = find(stderr, &["error"])
+    {
+        return Some(summary(
+            lines,
+            error,
+            error,
+            "compiletest",
+            Confidence::High,
+        ));
+    }
+    if let Some(diff) = find(start, &["diff of "])
+        && let Some(change) = find(diff, &["-\t", "+\t"])
+    {
+        return Some(summary(
+            lines,
+            diff,
+            change,
+            "compiletest",
+            Confidence::High,
+        ));
+    }
+    let error = find(start, &["error"])?;
+    Some(summary(
+        lines,
+        error,
+        error,
+        "compiletest",
+        Confidence::Medium,
+    ))
+}
+
+/// Finds the line that best describes the failure with the rules.
+///
+/// Each priority of rules gets a pass over the whole log, in order.
+pub fn error_summary<'a>(log: &'a Log, rules: &Rules) -> Option<(ErrorSummary, &'a Line)> {
+    let lines = &log.lines;
+    for pass in rules.error_line.chunk_by(|a, b| a.priority == b.priority) {
+        // A match that wants the line after it reported.
+        let mut previous: Option<(&ErrorLineRule, usize)> = None;
+        for (i, line) in lines.iter().enumerate() {
+            if let Some((rule, matched)) = previous {
+                let headline = if rule.unless_next.iter().any(|p| p.matches(&line.text)) {
+                    matched
+                } else {
+                    i
+                };
+                return Some(summary(
+                    lines,
+                    matched,
+                    headline,
+                    &rule.name,
+                    rule.confidence,
+                ));
+            }
+            if let Some(rule) = pass.iter().find(|rule| rule.matches(&line.text)) {
+                match rule.report {
+                    Report::Line => return Some(summary(lines, i, i, &rule.name, rule.confidence)),
+                    Report::Next => previous = Some((rule, i)),
+                }
+            }
+        }
+        // The match was on the last line, so there's nothing after it.
+        if let Some((rule, matched)) = previous {
+            return Some(summary(
+                lines,
+                matched,
+                matched,
+                &rule.name,
+                rule.confidence,
+            ));
+        }
+    }
+    None
+}
@@ -1,25 +1,26 @@
+use crate::cache;
+use crate::strip_ansi::strip_ansi;
 use serde::Deserialize;
 use std::error::Error;
 use std::fmt;
-use std::io;
-use std::process::{self, Command};
-use std::string::FromUtf8Error;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{self, Child, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
 
 #[derive(Debug)]
 pub enum GhError {
     Io(io::Error),
     Failed(process::Output),
-    Unicode(FromUtf8Error),
 }
 
 impl fmt::Display for GhError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Io(e) => e.fmt(f),
-            Self::Unicode(e) => e.fmt(f),
             Self::Failed(output) => {
                 f.write_fmt(format_args!("gh {}\n", output.status))?;
-                f.write_str(&String::from_utf8_lossy(&output.stderr))
+                f.write_str(&String::from_utf8_lossy(&strip_ansi(&output.stderr)))
             }
         }
     }
@@ -59,12 +60,62 @@ impl GithubApi {
         self
     }
 
+    /// Runs the command, returning its output as text.
+    ///
+    /// Anything that isn't valid UTF-8 is replaced rather than being an error.
     pub fn run(&mut self) -> Result<String, GhError> {
         self.raw_output()
-            .and_then(|output| String::from_utf8(output).map_err(GhError::Unicode))
+            .map(|output| String::from_utf8_lossy(&output).into_owned())
     }
 
     pub fn raw_output(&mut self) -> Result<Vec<u8>, GhError> {
+        match self.command().output() {
+            Ok(output) => {
+                if output.status.success() {
+                    Ok(output.stdout)
+                } else {
+                    Err(GhError::Failed(output))
+                }
+            }
+            Err(e) => Err(GhError::Io(e)),
+        }
+    }
+
+    /// Streams the output rather than collecting it all in memory.
+    pub fn stream(&mut self) -> Result<GhStream, GhError> {
+        let mut child = self
+            .command()
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(GhError::Io)?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        // Read on another thread, as `gh` would block if the pipe filled up
+        // while we're reading stdout.
+        let mut pipe = child.stderr.take().expect("stderr is piped");
+        let stderr = thread::spawn(move || {
+            let mut stderr = Vec::new();
+            let _ = pipe.read_to_end(&mut stderr);
+            stderr
+        });
+        Ok(GhStream {
+            child,
+            stdout,
+            stderr: Some(stderr),
+        })
+    }
+
+    /// Streams the output straight into a cache entry.
+    pub fn download(&mut self, path: &Path) -> Result<(), GhError> {
+        let mut stream = self.stream()?;
+        let mut writer = cache::Writer::new(path).map_err(GhError::Io)?;
+        // On error, dropping the stream stops `gh`.
+        io::copy(&mut stream, &mut writer).map_err(GhError::Io)?;
+        stream.finish()?;
+        writer.finish().map_err(GhError::Io)
+    }
+
+    fn command(&self) -> Command {
         let mut cmd = Command::new("gh");
         cmd.args(["api", &self.api]);
         cmd.args(["--method", "GET"]);
@@ -87,17 +138,52 @@ impl GithubApi {
         if self.all_pages {
             cmd.args(["--paginate", "--slurp"]);
         }
+        cmd
+    }
+}
 
-        match cmd.output() {
-            Ok(output) => {
-                if output.status.success() {
-                    Ok(output.stdout)
-                } else {
-                    Err(GhError::Failed(output))
-                }
-            }
-            Err(e) => Err(GhError::Io(e)),
+/// The output of a running `gh` command.
+///
+/// If it's dropped before [`GhStream::finish`] is called then `gh` is killed.
+pub struct GhStream {
+    child: Child,
+    stdout: ChildStdout,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+}
+
+impl GhStream {
+    /// Waits for `gh` to exit, returning an error if it failed.
+    ///
+    /// This should only be called after reading all the output.
+    pub fn finish(mut self) -> Result<(), GhError> {
+        let status = self.child.wait().map_err(GhError::Io)?;
+        let stderr = self
+            .stderr
+            .take()
+            .and_then(|stderr| stderr.join().ok())
+            .unwrap_or_default();
+        if status.success() {
+            return Ok(());
         }
+        Err(GhError::Failed(process::Output {
+            status,
+            stdout: Vec::new(),
+            stderr,
+        }))
+    }
+}
+
+impl Read for GhStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for GhStream {
+    fn drop(&mut self) {
+        // Does nothing if `gh` has already exited.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
     }
 }
 
@@ -144,9 +230,22 @@ pub struct Job {
     pub steps: Vec<Step>,
 }
 
+impl Job {
+    /// The step whose log we want from a failed job.
+    ///
+    /// Usually this is the step that failed but if the job timed out then the
+    /// step that was running gets cancelled instead.
+    pub fn failed_step(&self) -> Option<&Step> {
+        let find = |conclusion| self.steps.iter().find(|s| s.conclusion == conclusion);
+        find(Conclusion::Failure).or_else(|| find(Conclusion::Cancelled))
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Step {
     pub name: String,
     pub conclusion: Conclusion,
     pub number: u64,
+    /// Skipped steps don't have a start time.
+    pub started_at: Option<String>,
 }
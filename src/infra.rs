@@ -0,0 +1,149 @@
+//! Telling failures that are the machine's fault apart from those that are
+//! the code's.
+
+use crate::log::Line;
+use serde::{Deserialize, Serialize};
+
+/// Why a job failed, when it wasn't the code.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    RunnerShutdown,
+    RunnerLostCommunication,
+    DiskFull,
+    OutOfMemory,
+    DockerPull,
+    Cancelled,
+}
+
+impl Category {
+    /// What's in the log for each category, most telling first. If there's
+    /// more than one then the first in this list wins, as e.g. a shutdown
+    /// cancels whatever was running.
+    const MESSAGES: [(&str, Category); 12] = [
+        (
+            "The runner has received a shutdown signal",
+            Category::RunnerShutdown,
+        ),
+        (
+            "lost communication with the server",
+            Category::RunnerLostCommunication,
+        ),
+        ("No space left on device", Category::DiskFull),
+        // 128 + SIGKILL, which is nearly always the OOM killer.
+        (
+            "Process completed with exit code 137.",
+            Category::OutOfMemory,
+        ),
+        ("Out of memory: Killed process", Category::OutOfMemory),
+        ("Killed signal terminated program", Category::OutOfMemory),
+        ("toomanyrequests: ", Category::DockerPull),
+        ("pull access denied for ", Category::DockerPull),
+        (
+            "Error response from daemon: Get \"https://",
+            Category::DockerPull,
+        ),
+        ("failed to pull image", Category::DockerPull),
+        ("Error: failed to pull", Category::DockerPull),
+        ("The operation was canceled.", Category::Cancelled),
+    ];
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::RunnerShutdown => "runner shut down",
+            Self::RunnerLostCommunication => "runner lost communication",
+            Self::DiskFull => "no space left on device",
+            Self::OutOfMemory => "out of memory",
+            Self::DockerPull => "docker pull failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Classifies the part of the log the failure is in. The rest of the log is
+/// left alone, as a message there may well have been about something that
+/// was retried or didn't matter.
+pub fn find(lines: &[Line]) -> Option<Category> {
+    let mut best: Option<usize> = None;
+    for line in lines {
+        let end = best.unwrap_or(Category::MESSAGES.len());
+        if let Some(i) = Category::MESSAGES[..end]
+            .iter()
+            .position(|(message, _)| line.text.contains(message))
+        {
+            best = Some(i);
+        }
+    }
+    best.map(|i| Category::MESSAGES[i].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::Log;
+
+    fn classify(log: &str) -> Option<Category> {
+        find(&Log::parse(log).lines)
+    }
+
+    #[test]
+    fn runner_shutdown() {
+        assert_eq!(
+            classify(
+                "\
+test [ui] tests/ui/foo.rs ... ok
+##[error]The runner has received a shutdown signal. This can happen when the runner service is stopped, or a manually started runner is canceled.
+##[error]The operation was canceled.
+"
+            ),
+            Some(Category::RunnerShutdown)
+        );
+    }
+
+    #[test]
+    fn disk_full() {
+        assert_eq!(
+            classify(
+                "\
+error: failed to write /checkout/obj/build/foo.rlib: No space left on device (os error 28)
+##[error]Process completed with exit code 1.
+"
+            ),
+            Some(Category::DiskFull)
+        );
+    }
+
+    #[test]
+    fn out_of_memory() {
+        assert_eq!(
+            classify("##[error]Process completed with exit code 137."),
+            Some(Category::OutOfMemory)
+        );
+    }
+
+    #[test]
+    fn most_telling_wins() {
+        // Being cancelled is what a shutdown looks like to the step.
+        assert_eq!(
+            classify(
+                "\
+##[error]The operation was canceled.
+##[error]The runner has received a shutdown signal.
+"
+            ),
+            Some(Category::RunnerShutdown)
+        );
+    }
+
+    #[test]
+    fn code_failures() {
+        assert_eq!(
+            classify(
+                "error[E0308]: mismatched types\n##[error]Process completed with exit code 1."
+            ),
+            None
+        );
+        // A Rust allocation failure is usually a bug in the code.
+        assert_eq!(classify("memory allocation of 1024 bytes failed"), None);
+    }
+}
@@ -0,0 +1,286 @@
+//! Reading the failed tests out of libtest's output.
+//!
+//! When tests fail, libtest prints the captured output of each failed test,
+//! then a list of their names and a summary:
+//!
+//! ```text
+//! failures:
+//!
+//! ---- tests::foo stdout ----
+//! thread 'tests::foo' panicked at src/lib.rs:10:5:
+//! assertion failed: false
+//! note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+//!
+//!
+//! failures:
+//!     tests::foo
+//!
+//! test result: FAILED. 0 passed; 1 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.00s
+//! ```
+
+use crate::compiletest::{self, CompiletestFailure};
+use crate::log::Line;
+use crate::panic;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::mem;
+
+/// The most failed tests that are kept from a log. A broken compiler can fail
+/// every UI test, and the rest are much the same as the first.
+const MAX_TESTS: usize = 50;
+/// The most lines kept from the start of a test's output, which is where
+/// compiletest says what went wrong.
+const MAX_HEAD_LINES: usize = 50;
+/// The most lines kept from the end of a test's output, which is where it
+/// usually panicked.
+const MAX_TAIL_LINES: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TestFailure {
+    pub name: String,
+    /// What the test printed, without timestamps or colours. Empty if it
+    /// printed nothing, e.g. a `#[should_panic]` test that didn't panic.
+    pub stdout: String,
+    pub panic_message: Option<String>,
+    /// Where the test panicked, e.g. `src/lib.rs:10:5`.
+    pub location: Option<String>,
+    /// More about the failure, if it's a compiletest test.
+    pub compiletest: Option<CompiletestFailure>,
+}
+
+enum State {
+    Other,
+    /// In the `---- name stdout ----` section of a test.
+    Output {
+        name: String,
+        output: Output,
+    },
+    /// In a `failures:` list.
+    List,
+}
+
+/// The start and end of a test's output.
+#[derive(Default)]
+struct Output {
+    head: Vec<String>,
+    tail: VecDeque<String>,
+    /// How many lines were left out between the two.
+    omitted: usize,
+}
+
+impl Output {
+    fn push(&mut self, line: &str) {
+        if self.head.len() < MAX_HEAD_LINES {
+            self.head.push(line.into());
+            return;
+        }
+        if self.tail.len() == MAX_TAIL_LINES {
+            self.tail.pop_front();
+            self.omitted += 1;
+        }
+        self.tail.push_back(line.into());
+    }
+
+    fn into_lines(self) -> Vec<String> {
+        let mut lines = self.head;
+        if self.omitted > 0 {
+            lines.push(format!("… {} lines left out", self.omitted));
+        }
+        lines.extend(self.tail);
+        lines
+    }
+}
+
+/// Finds the failed tests in a log as it's read.
+///
+/// A log can have the output of any number of test binaries. Only the first
+/// [`MAX_TESTS`] failed tests are kept, so memory use is bounded however many
+/// fail.
+pub struct Parser {
+    state: State,
+    /// The tests with output from the binary being read.
+    current: Vec<TestFailure>,
+    /// The names listed by the binary being read.
+    listed: Vec<String>,
+    done: Vec<TestFailure>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self {
+            state: State::Other,
+            current: Vec::new(),
+            listed: Vec::new(),
+            done: Vec::new(),
+        }
+    }
+}
+
+impl Parser {
+    pub fn push(&mut self, line: &Line) {
+        let text = line.text.as_str();
+        if let Some(name) = section_name(text) {
+            self.end_section();
+            self.state = if self.is_full() {
+                State::Other
+            } else {
+                State::Output {
+                    name: name.into(),
+                    output: Output::default(),
+                }
+            };
+        } else if text == "failures:" {
+            self.end_section();
+            self.state = State::List;
+        } else if text.starts_with("test result: ") {
+            self.end_section();
+            self.end_binary();
+        } else {
+            match &mut self.state {
+                State::Output { output, .. } => output.push(text),
+                State::List => {
+                    if let Some(name) = text.strip_prefix("    ") {
+                        if self.listed.len() < MAX_TESTS {
+                            self.listed.push(name.trim().into());
+                        }
+                    } else if !text.trim().is_empty() {
+                        self.state = State::Other;
+                    }
+                }
+                State::Other => {}
+            }
+        }
+    }
+
+    fn end_section(&mut self) {
+        let State::Output { name, output } = mem::replace(&mut self.state, State::Other) else {
+            return;
+        };
+        let mut lines = output.into_lines();
+        // Tests are separated by blank lines, which aren't part of the output.
+        while lines.last().is_some_and(|l| l.trim().is_empty()) {
+            lines.pop();
+        }
+        let panic = panic::find(lines.iter().map(String::as_str));
+        let (panic_message, location) = match panic {
+            Some(panic) => (panic.message, panic.location),
+            None => (None, None),
+        };
+        self.current.push(TestFailure {
+            compiletest: compiletest::parse(&name, &lines),
+            name,
+            stdout: lines.join("\n"),
+            panic_message,
+            location,
+        });
+    }
+
+    /// Tests that printed nothing are only in the list of names.
+    fn end_binary(&mut self) {
+        for name in mem::take(&mut self.listed) {
+            if !self.is_full() && !self.current.iter().any(|t| t.name == name) {
+                self.current.push(TestFailure {
+                    compiletest: compiletest::parse(&name, &[]),
+                    name,
+                    stdout: String::new(),
+                    panic_message: None,
+                    location: None,
+                });
+            }
+        }
+        self.done.append(&mut self.current);
+        self.state = State::Other;
+    }
+
+    fn is_full(&self) -> bool {
+        self.done.len() + self.current.len() >= MAX_TESTS
+    }
+
+    /// The failed tests, in the order they were printed. The output may have
+    /// been cut off so anything unfinished is included too.
+    pub fn finish(mut self) -> Vec<TestFailure> {
+        self.end_section();
+        self.end_binary();
+        self.done
+    }
+}
+
+/// The name from a `---- name stdout ----` line.
+pub fn section_name(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix("---- ")?;
+    rest.strip_suffix(" stdout ----")
+        .or_else(|| rest.strip_suffix(" stderr ----"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::Log;
+
+    fn parse(log: &str) -> Vec<TestFailure> {
+        let mut parser = Parser::default();
+        for line in &Log::parse(log).lines {
+            parser.push(line);
+        }
+        parser.finish()
+    }
+
+    #[test]
+    fn one_failure() {
+        let tests = parse(
+            "\
+failures:
+
+---- tests::foo stdout ----
+thread 'tests::foo' panicked at src/lib.rs:10:5:
+assertion failed: false
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+
+
+failures:
+    tests::foo
+    tests::quiet
+
+test result: FAILED. 0 passed; 2 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.00s
+",
+        );
+        assert_eq!(tests.len(), 2);
+        assert_eq!(tests[0].name, "tests::foo");
+        assert_eq!(tests[0].location.as_deref(), Some("src/lib.rs:10:5"));
+        assert_eq!(
+            tests[0].panic_message.as_deref(),
+            Some("assertion failed: false")
+        );
+        assert_eq!(tests[1].name, "tests::quiet");
+        assert_eq!(tests[1].stdout, "");
+    }
+
+    #[test]
+    fn keeps_a_bounded_amount() {
+        let mut log = String::from("failures:\n");
+        for test in 0..MAX_TESTS * 2 {
+            log.push_str(&format!("---- t{test} stdout ----\n"));
+            for line in 0..1000 {
+                log.push_str(&format!("output {line}\n"));
+            }
+        }
+        log.push_str("\nfailures:\n");
+        for test in 0..MAX_TESTS * 3 {
+            log.push_str(&format!("    t{test}\n"));
+        }
+        log.push_str("\ntest result: FAILED.\n");
+        let tests = parse(&log);
+        assert_eq!(tests.len(), MAX_TESTS);
+        let lines: Vec<_> = tests[0].stdout.lines().collect();
+        assert_eq!(lines.len(), MAX_HEAD_LINES + 1 + MAX_TAIL_LINES);
+        assert_eq!(lines[0], "output 0");
+        assert_eq!(
+            lines[MAX_HEAD_LINES],
+            format!(
+                "… {} lines left out",
+                1000 - MAX_HEAD_LINES - MAX_TAIL_LINES
+            )
+        );
+        assert_eq!(lines.last(), Some(&"output 999"));
+    }
+}
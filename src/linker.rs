@@ -0,0 +1,237 @@
+//! Reading linker failures out of a log.
+//!
+//! When linking fails rustc prints the whole linker command line, which can
+//! be thousands of characters long, followed by what the linker said:
+//!
+//! ```text
+//! error: linking with `cc` failed: exit status: 1
+//!   |
+//!   = note: LC_ALL="C" PATH="..." "cc" "-m64" "/tmp/rustcXXXX/symbols.o" ...
+//!   = note: /usr/bin/ld: foo.o: in function `main':
+//!           foo.c:(.text+0x5): undefined reference to `bar'
+//!           collect2: error: ld returned 1 exit status
+//! ```
+//!
+//! Native builds can fail to link without rustc being involved, so the
+//! errors of the linkers themselves are recognised on their own too.
+
+use serde::{Deserialize, Serialize};
+
+/// The most symbols, or objects, that are kept.
+const MAX_NAMES: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LinkerFailure {
+    /// The linker rustc ran, e.g. `cc` or `link.exe`.
+    pub linker: Option<String>,
+    /// The symbols that couldn't be found.
+    pub symbols: Vec<String>,
+    /// The object files that referred to them.
+    pub objects: Vec<String>,
+    /// The command line rustc ran the linker with.
+    pub command: Option<String>,
+}
+
+impl LinkerFailure {
+    fn add_symbol(&mut self, symbol: &str) {
+        add(&mut self.symbols, symbol);
+    }
+
+    fn add_object(&mut self, object: &str) {
+        add(&mut self.objects, object);
+    }
+}
+
+fn add(names: &mut Vec<String>, name: &str) {
+    let name = name.trim();
+    if !name.is_empty() && names.len() < MAX_NAMES && !names.iter().any(|n| n == name) {
+        names.push(name.into());
+    }
+}
+
+/// A linker failure and where it is in the log.
+pub struct Found {
+    pub failure: LinkerFailure,
+    /// The first line with an error from the linker itself, or rustc's
+    /// `linking with` line if there's none.
+    pub error_line: usize,
+}
+
+/// Finds a linker failure in the lines, if there is one.
+pub fn find<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<Found> {
+    let mut failure = LinkerFailure::default();
+    // rustc's `linking with` line.
+    let mut header = None;
+    let mut error_line = None;
+    // Whether the linker's command line is next, just after the error.
+    let mut command_next = false;
+    // Whether we're in the list of undefined symbols ld64 gives.
+    let mut in_ld64_list = false;
+    for (i, line) in lines.into_iter().enumerate() {
+        let text = line.trim();
+        if let Some(rest) = text.strip_prefix("error: linking with `")
+            && let Some((linker, _)) = rest.split_once("` failed")
+        {
+            header.get_or_insert(i);
+            failure.linker.get_or_insert_with(|| linker.into());
+            command_next = true;
+            continue;
+        }
+        if let Some(note) = text.strip_prefix("= note: ") {
+            if command_next {
+                failure.command.get_or_insert_with(|| note.into());
+                command_next = false;
+                continue;
+            }
+        } else if text != "|" {
+            command_next = false;
+        }
+        // The line may still be part of a note.
+        let text = text.strip_prefix("= note: ").unwrap_or(text);
+
+        // GNU ld: ``foo.c:(.text+0x5): undefined reference to `bar'``
+        if let Some((_, rest)) = text.split_once("undefined reference to `")
+            && let Some((symbol, _)) = rest.split_once('\'')
+        {
+            error_line.get_or_insert(i);
+            failure.add_symbol(symbol);
+        }
+        // GNU ld: ``/usr/bin/ld: foo.o: in function `main':``
+        if let Some((before, _)) = text.split_once(": in function `") {
+            failure.add_object(before.rsplit_once(": ").map_or(before, |(_, o)| o));
+        }
+        // MSVC: `foo.o : error LNK2019: unresolved external symbol bar referenced in function main`
+        if let Some((object, rest)) = text.split_once(" : error LNK") {
+            error_line.get_or_insert(i);
+            failure.add_object(object);
+            if let Some((_, rest)) = rest.split_once("unresolved external symbol ") {
+                failure.add_symbol(rest.split_whitespace().next().unwrap_or(""));
+            }
+        } else if text.contains(" : fatal error LNK") {
+            // This is about the output, e.g. `foo.exe : fatal error LNK1120: 1 unresolved externals`.
+            error_line.get_or_insert(i);
+        }
+        // LLD: `rust-lld: error: undefined symbol: bar`
+        if let Some((_, symbol)) = text.split_once("error: undefined symbol: ") {
+            error_line.get_or_insert(i);
+            failure.add_symbol(symbol);
+        }
+        // LLD: `>>>               foo.o:(main)`
+        if let Some(rest) = text.strip_prefix(">>>")
+            && let Some((object, _)) = rest.split_once(":(")
+        {
+            failure.add_object(object);
+        }
+        // ld64:
+        //
+        // Undefined symbols for architecture arm64:
+        //   "_bar", referenced from:
+        //       _main in foo.o
+        // ld: symbol(s) not found for architecture arm64
+        if text.starts_with("Undefined symbols for architecture ") {
+            error_line.get_or_insert(i);
+            in_ld64_list = true;
+        } else if in_ld64_list {
+            if let Some(rest) = text.strip_prefix('"')
+                && let Some((symbol, _)) = rest.split_once("\", referenced from:")
+            {
+                failure.add_symbol(symbol);
+            } else if let Some((_, object)) = text.split_once(" in ") {
+                failure.add_object(object);
+            } else {
+                in_ld64_list = false;
+            }
+        }
+    }
+    Some(Found {
+        failure,
+        error_line: error_line.or(header)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_in(log: &str) -> Found {
+        find(log.lines()).expect("a linker failure")
+    }
+
+    #[test]
+    fn gnu_ld() {
+        let found = find_in(
+            "\
+error: linking with `cc` failed: exit status: 1
+  |
+  = note: LC_ALL=\"C\" PATH=\"...\" \"cc\" \"-m64\" \"/tmp/rustcXXXX/symbols.o\"
+  = note: /usr/bin/ld: foo.o: in function `main':
+          foo.c:(.text+0x5): undefined reference to `bar'
+          collect2: error: ld returned 1 exit status
+",
+        );
+        let failure = found.failure;
+        assert_eq!(found.error_line, 4);
+        assert_eq!(failure.linker.as_deref(), Some("cc"));
+        assert_eq!(failure.symbols, ["bar"]);
+        assert_eq!(failure.objects, ["foo.o"]);
+        assert!(failure.command.unwrap().starts_with("LC_ALL=\"C\""));
+    }
+
+    #[test]
+    fn msvc() {
+        let found = find_in(
+            "\
+error: linking with `link.exe` failed: exit code: 1120
+  |
+  = note: \"link.exe\" \"/NOLOGO\" \"foo.o\"
+  = note: foo.o : error LNK2019: unresolved external symbol bar referenced in function main
+          foo.exe : fatal error LNK1120: 1 unresolved externals
+",
+        );
+        let failure = found.failure;
+        assert_eq!(found.error_line, 3);
+        assert_eq!(failure.linker.as_deref(), Some("link.exe"));
+        assert_eq!(failure.symbols, ["bar"]);
+        assert_eq!(failure.objects, ["foo.o"]);
+    }
+
+    #[test]
+    fn lld_without_rustc() {
+        let found = find_in(
+            "\
+[10/20] Linking CXX executable bin/foo
+rust-lld: error: undefined symbol: bar
+>>> referenced by foo.c:5
+>>>               foo.o:(main)
+",
+        );
+        let failure = found.failure;
+        assert_eq!(found.error_line, 1);
+        assert_eq!(failure.linker, None);
+        assert_eq!(failure.symbols, ["bar"]);
+        assert_eq!(failure.objects, ["foo.o"]);
+    }
+
+    #[test]
+    fn ld64() {
+        let found = find_in(
+            "\
+Undefined symbols for architecture arm64:
+  \"_bar\", referenced from:
+      _main in foo.o
+ld: symbol(s) not found for architecture arm64
+",
+        );
+        assert_eq!(found.error_line, 0);
+        assert_eq!(found.failure.symbols, ["_bar"]);
+        assert_eq!(found.failure.objects, ["foo.o"]);
+    }
+
+    #[test]
+    fn only_linker_errors() {
+        assert!(
+            find("error: expected `;`, found `}`\nerror: aborting due to 1 previous error".lines())
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,413 @@
+//! A parsed model of a GitHub Actions log.
+//!
+//! Each line keeps the timestamp GitHub prefixed it with, has ANSI escapes
+//! removed and is classified by the workflow command it starts with, if any.
+//! The colours set by the escapes are kept separately from the text.
+//! Groups are built from the `##[group]` and `##[endgroup]` commands.
+
+use crate::sgr::{Interpreter, Style};
+use jiff::Timestamp;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::mem;
+use std::ops::Range;
+
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub time: Option<Timestamp>,
+    /// The text of the line, including any workflow command.
+    pub text: String,
+    pub kind: LineKind,
+    /// The number of the step the line belongs to, if known.
+    pub step: Option<u64>,
+    /// Where the line starts in the raw log, in bytes. The text may be
+    /// shorter, or even longer, than the raw line so this is the only way to
+    /// find it again.
+    pub offset: u64,
+    /// The styled parts of the text, in order. Text not covered by any is
+    /// unstyled.
+    pub styles: Vec<StyledSpan>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StyledSpan {
+    /// The byte range in the line's text.
+    pub range: Range<usize>,
+    pub style: Style,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineKind {
+    Text,
+    /// `##[group]`
+    GroupStart,
+    /// `##[endgroup]`
+    GroupEnd,
+    /// `##[error]`
+    Error,
+    /// `##[warning]`
+    Warning,
+}
+
+impl LineKind {
+    const COMMANDS: [(&str, LineKind); 4] = [
+        ("##[group]", LineKind::GroupStart),
+        ("##[endgroup]", LineKind::GroupEnd),
+        ("##[error]", LineKind::Error),
+        ("##[warning]", LineKind::Warning),
+    ];
+
+    fn of(text: &str) -> Self {
+        Self::COMMANDS
+            .iter()
+            .find(|(command, _)| text.starts_with(command))
+            .map_or(Self::Text, |&(_, kind)| kind)
+    }
+
+    fn command(self) -> &'static str {
+        Self::COMMANDS
+            .iter()
+            .find(|&&(_, kind)| kind == self)
+            .map_or("", |(command, _)| command)
+    }
+}
+
+impl Line {
+    /// Parses a line of the raw log, which may start with a timestamp.
+    ///
+    /// Styles set by ANSI escapes can span lines so the state is carried in
+    /// `ansi`. Escapes are removed before decoding as they aren't necessarily
+    /// UTF-8 either.
+    fn parse(line: &[u8], ansi: &mut Interpreter) -> Self {
+        let (time, line) = match split_timestamp(line) {
+            Some((time, text)) => (Some(time), text),
+            None => (None, line),
+        };
+        // The text split into runs of the same style.
+        let mut runs: Vec<(Style, Vec<u8>)> = Vec::new();
+        // What's been shown before the last carriage return.
+        let mut shown = Vec::new();
+        for &b in line {
+            if !ansi.update(b) {
+                continue;
+            }
+            if b == b'\r' {
+                // Progress bars go back to the start of the line to redraw it.
+                shown = overwrite(shown, decode(mem::take(&mut runs)));
+                continue;
+            }
+            match runs.last_mut() {
+                Some((style, run)) if *style == ansi.style => run.push(b),
+                _ => runs.push((ansi.style, vec![b])),
+            }
+        }
+        ansi.end_line();
+        let mut text = String::with_capacity(line.len());
+        let mut styles = Vec::new();
+        for (style, run) in overwrite(shown, decode(runs)) {
+            let start = text.len();
+            text.push_str(&run);
+            if !style.is_default() {
+                styles.push(StyledSpan {
+                    range: start..text.len(),
+                    style,
+                });
+            }
+        }
+        Self {
+            time,
+            kind: LineKind::of(&text),
+            text,
+            step: None,
+            offset: 0,
+            styles,
+        }
+    }
+
+    /// Splits the text into its parts, each with its style.
+    pub fn styled_parts(&self) -> impl Iterator<Item = (&str, Style)> {
+        let mut parts = Vec::new();
+        let mut pos = 0;
+        for span in &self.styles {
+            if pos < span.range.start {
+                parts.push((&self.text[pos..span.range.start], Style::default()));
+            }
+            parts.push((&self.text[span.range.clone()], span.style));
+            pos = span.range.end;
+        }
+        if pos < self.text.len() {
+            parts.push((&self.text[pos..], Style::default()));
+        }
+        parts.into_iter()
+    }
+
+    /// The text after the workflow command, e.g. the name of a group.
+    pub fn message(&self) -> &str {
+        &self.text[self.kind.command().len()..]
+    }
+}
+
+fn decode(runs: Vec<(Style, Vec<u8>)>) -> Vec<(Style, String)> {
+    runs.into_iter()
+        .map(|(style, run)| (style, String::from_utf8_lossy(&run).into_owned()))
+        .collect()
+}
+
+/// Writes `over` on top of `under`, like a terminal does when a line is
+/// redrawn after a carriage return. Anything in `under` past the end of
+/// `over` is still shown.
+fn overwrite(under: Vec<(Style, String)>, mut over: Vec<(Style, String)>) -> Vec<(Style, String)> {
+    if under.is_empty() {
+        return over;
+    }
+    let mut skip: usize = over.iter().map(|(_, run)| run.chars().count()).sum();
+    for (style, run) in under {
+        match run.char_indices().nth(skip) {
+            Some((i, _)) => {
+                over.push((style, run[i..].into()));
+                skip = 0;
+            }
+            None => skip -= run.chars().count(),
+        }
+    }
+    over
+}
+
+/// Splits the timestamp GitHub adds to the start of every log line from the text.
+pub fn split_timestamp(line: &[u8]) -> Option<(Timestamp, &[u8])> {
+    let space = line.iter().position(|&b| b == b' ')?;
+    let head = str::from_utf8(&line[..space]).ok()?;
+    // FIXME: Exactly specify the expected format
+    Some((head.parse().ok()?, &line[space + 1..]))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Log {
+    pub lines: Vec<Line>,
+}
+
+/// A `##[group]` section of the log.
+#[derive(Debug)]
+pub struct Group {
+    pub name: String,
+    /// The lines in the group, starting with the `##[group]` line and
+    /// including the `##[endgroup]` line, if any.
+    pub lines: Range<usize>,
+    pub children: Vec<Group>,
+}
+
+impl Log {
+    pub fn parse(text: &str) -> Self {
+        let lines = Lines::new(text.as_bytes());
+        Self {
+            lines: lines
+                .map(|line| line.expect("reading from memory can't fail"))
+                .collect(),
+        }
+    }
+
+    /// The text of the log, without timestamps or colours.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for line in &self.lines {
+            text.push_str(&line.text);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Displays the log with its colours, as ANSI escapes.
+    pub fn styled(&self) -> Styled<'_> {
+        Styled(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The tree of groups in the log.
+    ///
+    /// A group that's never ended runs until the end of the log.
+    pub fn groups(&self) -> Vec<Group> {
+        fn close(group: Group, stack: &mut [Group], roots: &mut Vec<Group>) {
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(group),
+                None => roots.push(group),
+            }
+        }
+
+        let mut roots = Vec::new();
+        let mut stack: Vec<Group> = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            match line.kind {
+                LineKind::GroupStart => stack.push(Group {
+                    name: line.message().into(),
+                    lines: i..self.lines.len(),
+                    children: Vec::new(),
+                }),
+                LineKind::GroupEnd => {
+                    if let Some(mut group) = stack.pop() {
+                        group.lines.end = i + 1;
+                        close(group, &mut stack, &mut roots);
+                    }
+                }
+                _ => {}
+            }
+        }
+        while let Some(group) = stack.pop() {
+            close(group, &mut stack, &mut roots);
+        }
+        roots
+    }
+}
+
+/// Writes the log back out in the same format it was parsed from, keeping the
+/// timestamps.
+impl fmt::Display for Log {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(time) = line.time {
+                write!(f, "{time} ")?;
+            }
+            f.write_str(&line.text)?;
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// The most bytes of a line that are kept. The rest of a longer line is
+/// skipped, so a log without line breaks can't fill the memory.
+pub const MAX_LINE_BYTES: u64 = 64 * 1024;
+
+/// Parses a log a line at a time, so it never has to be held in memory.
+///
+/// Logs are bytes and not necessarily UTF-8. Test output can contain anything,
+/// so each line is decoded lossily. Lines are cut at [`MAX_LINE_BYTES`].
+pub struct Lines<R> {
+    reader: R,
+    buf: Vec<u8>,
+    ansi: Interpreter,
+    /// How far into the log we are, in bytes.
+    offset: u64,
+}
+
+impl<R: BufRead> Lines<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            ansi: Interpreter::default(),
+            offset: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = io::Result<Line>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buf.clear();
+        let offset = self.offset;
+        match (&mut self.reader)
+            .take(MAX_LINE_BYTES)
+            .read_until(b'\n', &mut self.buf)
+        {
+            Ok(0) => return None,
+            Ok(n) => self.offset += n as u64,
+            Err(e) => return Some(Err(e)),
+        }
+        if !self.buf.ends_with(b"\n") {
+            match skip_line(&mut self.reader) {
+                Ok(n) => self.offset += n,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        // Line endings are stripped the same way as `str::lines`.
+        let mut line = &self.buf[..];
+        if let Some(rest) = line.strip_suffix(b"\n") {
+            line = rest.strip_suffix(b"\r").unwrap_or(rest);
+        }
+        let mut line = Line::parse(line, &mut self.ansi);
+        line.offset = offset;
+        Some(Ok(line))
+    }
+}
+
+/// Skips to the start of the next line, without keeping what's skipped.
+/// Returns how many bytes were skipped.
+fn skip_line(reader: &mut impl BufRead) -> io::Result<u64> {
+    let mut skipped = 0;
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if buf.is_empty() {
+            return Ok(skipped);
+        }
+        let (n, done) = match buf.iter().position(|&b| b == b'\n') {
+            Some(i) => (i + 1, true),
+            None => (buf.len(), false),
+        };
+        reader.consume(n);
+        skipped += n as u64;
+        if done {
+            return Ok(skipped);
+        }
+    }
+}
+
+/// See [`Log::styled`].
+pub struct Styled<'a>(&'a Log);
+
+impl fmt::Display for Styled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.0.lines {
+            if let Some(time) = line.time {
+                write!(f, "{time} ")?;
+            }
+            for (text, style) in line.styled_parts() {
+                if style.is_default() {
+                    f.write_str(text)?;
+                } else {
+                    // Reset after each part so styles never carry over lines.
+                    write!(f, "{style}{text}\x1b[0m")?;
+                }
+            }
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(log: &[u8]) -> Vec<String> {
+        Lines::new(log).map(|l| l.unwrap().text).collect()
+    }
+
+    #[test]
+    fn stray_control_string() {
+        // 0x9D starts an OSC, which only ends with BEL or ST.
+        let log =
+            b"2025-04-01T00:00:05.0Z dump: \x00\x9d\x01\n2025-04-01T00:00:06.0Z error: boom\n";
+        assert_eq!(texts(log), ["dump: \0", "error: boom"]);
+        assert_eq!(
+            texts(b"\x1b]8;;https://example.com\nerror: boom\n"),
+            ["", "error: boom"]
+        );
+    }
+
+    #[test]
+    fn style_spans_lines() {
+        let lines: Vec<_> = Lines::new(&b"\x1b[31mred\nstill red\x1b[0m\n"[..])
+            .map(|l| l.unwrap())
+            .collect();
+        assert_eq!(lines[1].text, "still red");
+        assert_eq!(lines[1].styles.len(), 1);
+    }
+}
@@ -1,18 +1,39 @@
-//!
-use clap::Parser;
+//! Generates a report of failed CI runs for rust-lang/rust.
+use clap::{Parser, Subcommand};
 
+mod bundle;
+mod cache;
+mod compiletest;
+mod elide;
+mod extract;
 mod github;
+mod infra;
+mod libtest;
+mod linker;
+mod log;
+mod native;
+mod network;
+mod panic;
+mod rules;
+mod run_logs;
+mod sgr;
+mod steps;
 mod strip_ansi;
+mod timing;
 
 use core::time::Duration;
 use jiff::Timestamp;
 use std::{
-    fs,
-    process::{Command, ExitCode},
+    collections::{BTreeMap, HashSet},
+    fmt, fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
-use strip_ansi::AnsiMode;
 
+use compiletest::Change;
 use github::{Conclusion, GithubApi, WorkflowRuns};
+use log::{Group, LineKind, Log};
 
 const FULL_LOGS: bool = cfg!(feature = "download_full_logs");
 
@@ -36,6 +57,15 @@ const DAYS: u64 = 24 * HOUR;
 
 fn main() -> ExitCode {
     let cli = Cli::parse();
+    match cli.command {
+        Some(Commands::Gc(args)) => return gc(&args),
+        Some(Commands::Cache(CacheArgs { command })) => return cache_command(command),
+        None => {}
+    }
+    let rules = match rules::Rules::load(cli.rules.as_deref()) {
+        Ok(rules) => rules,
+        Err(e) => fail!("rules error: {e}"),
+    };
     // FIXME: proper arg validation
     let start = cli
         .start_date
@@ -49,14 +79,29 @@ fn main() -> ExitCode {
     let end = end.strftime("%Y-%m-%d");
     let range = format!("{start}..{end}");
 
-    let run_dir = "cache/runs";
+    // Things we couldn't get from the cache in offline mode.
+    let mut missing = Vec::new();
+
+    let run_dir = cache::RUNS_DIR;
     let todays_cache = format!("{run_dir}/{range}.json");
     if let Err(e) = fs::create_dir_all(run_dir) {
         fail!("filesystem error: {e}\n in path {run_dir}");
     }
 
     // FIXME: improve caching
-    let runs = if !fs::exists(&todays_cache).unwrap_or(false) {
+    let cached = match cache::read_to_string(&todays_cache) {
+        Ok(cached) => cached,
+        Err(e) => fail!("filesystem error: {e}\n in path {todays_cache}"),
+    };
+    let runs = if let Some(runs) = cached {
+        runs
+    } else if cli.offline {
+        missing.push(Missing::Runs {
+            range: range.clone(),
+        });
+        // Carry on with no runs so we still produce a report.
+        String::from("[]")
+    } else {
         let result = GithubApi::new("repos/rust-lang-ci/rust/actions/runs")
             .fields([
                 "status=completed",
@@ -72,15 +117,10 @@ fn main() -> ExitCode {
             Err(e) => fail!("github error: {e}"),
         };
 
-        if let Err(e) = fs::write(&todays_cache, &runs) {
+        if let Err(e) = cache::write(&todays_cache, &runs) {
             fail!("filesystem error: {e}\n in path {todays_cache}");
         }
         runs
-    } else {
-        match fs::read_to_string(&todays_cache) {
-            Err(e) => fail!("filesystem error: {e}\n in path {todays_cache}"),
-            Ok(runs) => runs,
-        }
     };
 
     let runs: Vec<WorkflowRuns> = match serde_json::from_str(&runs) {
@@ -111,15 +151,15 @@ fn main() -> ExitCode {
         }
     }
 
-    let jobs_dir = "cache/jobs";
+    let jobs_dir = cache::JOBS_DIR;
     if let Err(e) = fs::create_dir_all(jobs_dir) {
         fail!("filesystem error: {e}\n in path {jobs_dir}");
     }
-    let run_logs_dir = "cache/logs/runs";
+    let run_logs_dir = cache::RUN_LOGS_DIR;
     if let Err(e) = fs::create_dir_all(run_logs_dir) {
         fail!("filesystem error: {e}\n in path {run_logs_dir}");
     }
-    let jobs_logs_dir = "cache/logs/jobs";
+    let jobs_logs_dir = cache::JOB_LOGS_DIR;
     if let Err(e) = fs::create_dir_all(jobs_logs_dir) {
         fail!("filesystem error: {e}\n in path {jobs_logs_dir}");
     }
@@ -133,12 +173,22 @@ fn main() -> ExitCode {
         fail: fail_count,
         cancelled: cancelled_count,
         fails: Vec::new(),
+        missing: Vec::new(),
     };
     for FailedWorkflowRun { id, title } in failures {
         println!("{id}: {title}");
 
         let job_path = format!("{jobs_dir}/{id}.json");
-        let jobs = if !fs::exists(&job_path).unwrap_or(false) {
+        let cached = match cache::read_to_string(&job_path) {
+            Ok(cached) => cached,
+            Err(e) => fail!("filesystem error: {e}\n in path {job_path}"),
+        };
+        let jobs = if let Some(jobs) = cached {
+            jobs
+        } else if cli.offline {
+            missing.push(Missing::Jobs { run_id: id, title });
+            continue;
+        } else {
             let result = GithubApi::new(&format!("repos/rust-lang-ci/rust/actions/runs/{id}/jobs"))
                 .field("per_page=100")
                 .run();
@@ -148,142 +198,253 @@ fn main() -> ExitCode {
                 Err(e) => fail!("github error: {e}"),
             };
 
-            if let Err(e) = fs::write(&job_path, &job) {
+            if let Err(e) = cache::write(&job_path, &job) {
                 fail!("filesystem error: {e}\n in path {job_path}");
             }
             job
-        } else {
-            match fs::read_to_string(&job_path) {
-                Err(e) => fail!("filesystem error: {e}\n in path {job_path}"),
-                Ok(jobs) => jobs,
-            }
         };
         let jobs: github::Jobs = match serde_json::from_str(&jobs) {
             Ok(jobs) => jobs,
             Err(e) => fail!("serde error: {e}"),
         };
 
+        // The processed logs for each failed job, with the path of the raw log.
+        let mut processed_jobs = Vec::new();
         if FULL_LOGS {
             // Download the full logs so we can select only the step that failed.
-            // This will produce very large zip files so not recommended.
+            // The archive is streamed and, unless asked to keep it, only the
+            // failed steps are cached.
             let run_logs_path = format!("{run_logs_dir}/{id}.zip");
-            if !fs::exists(&run_logs_path).unwrap_or(false) {
-                let result =
-                    GithubApi::new(&format!("repos/rust-lang-ci/rust/actions/runs/{id}/logs"))
-                        .raw_output();
-
-                let logs = match result {
-                    Ok(output) => output,
-                    Err(e) => fail!("github error: {e}"),
-                };
-
-                if let Err(e) = fs::write(&run_logs_path, &logs) {
-                    fail!("filesystem error: {e}\n in path {run_logs_path}");
-                }
+            let extract_dir = format!("{run_logs_dir}/{id}");
+            if let Err(e) = fs::create_dir_all(&extract_dir) {
+                fail!("filesystem error: {e}\n in path {extract_dir}");
             }
-
-            // extract the logs
-            let extract_dir = &run_logs_path[..run_logs_path.len() - 4];
-            if !fs::exists(extract_dir).unwrap_or(false) {
-                if let Err(e) = fs::create_dir_all(extract_dir) {
-                    fail!("filesystem error: {e}\n in path {jobs_logs_dir}");
+            // Failed steps that still need to be read from the archive.
+            let mut to_extract = Vec::new();
+            for job in &jobs.jobs {
+                // Skip success and bors.
+                if job.conclusion != Conclusion::Failure || job.name == "bors build finished" {
+                    continue;
                 }
-                let mut to_extract = String::new();
-                'jobs: for job in jobs.jobs {
-                    if job.conclusion == Conclusion::Failure {
-                        for step in job.steps {
-                            if step.conclusion == Conclusion::Failure {
-                                let job_name = &job.name;
-                                let step_number = step.number;
-                                let step_name = step.name;
-                                to_extract = format!("{job_name}/{step_number}_{step_name}.txt");
-                                break 'jobs;
-                            }
-                        }
+                let Some(step) = job.failed_step() else {
+                    eprintln!("no failed step found for job {}: {}", job.id, job.name);
+                    continue;
+                };
+                let job_id = job.id;
+                let processed_path = format!("{extract_dir}/{job_id}.processed.json");
+                let step_log_path = format!("{extract_dir}/{job_id}.txt");
+                match extract::load(&processed_path, &rules) {
+                    Ok(Some(processed)) => {
+                        // The report links to the log, so it's still in use.
+                        cache::touch(&step_log_path);
+                        processed_jobs.push((job, step_log_path, processed));
+                        continue;
                     }
+                    Ok(None) => {}
+                    Err(e) => fail!("filesystem error: {e}\n in path {processed_path}"),
                 }
-                if to_extract.is_empty() {
-                    let _ = fs::remove_dir_all(extract_dir);
-                    fail!("no failed logs for workflow {id}");
+                match cache::open(&step_log_path) {
+                    Ok(Some(log)) => match extract::process_step_log(log, job, step, &rules) {
+                        Ok(processed) => {
+                            if let Err(e) = extract::save(&processed_path, &processed) {
+                                fail!("filesystem error: {e}\n in path {processed_path}");
+                            }
+                            processed_jobs.push((job, step_log_path, processed));
+                        }
+                        Err(e) => fail!("filesystem error: {e}\n in path {step_log_path}"),
+                    },
+                    Ok(None) => to_extract.push((job, step)),
+                    Err(e) => fail!("filesystem error: {e}\n in path {step_log_path}"),
                 }
-                match Command::new("tar")
-                    .args(["-xf", &run_logs_path, "-C", extract_dir])
-                    .arg(&to_extract)
-                    .status()
-                {
-                    Ok(status) if !status.success() => {
-                        let _ = fs::remove_dir_all(extract_dir);
-                        fail!("tar failed to extract the archive at {run_logs_path}")
+            }
+
+            if !to_extract.is_empty() {
+                // Each step log is written straight to the cache as it's read.
+                let save_log = |job: &github::Job, log: &mut dyn io::Read| {
+                    let path = PathBuf::from(format!("{extract_dir}/{}.txt", job.id));
+                    let mut writer = cache::Writer::new(&path)?;
+                    io::copy(log, &mut writer)?;
+                    writer.finish()
+                };
+                let found = if fs::exists(&run_logs_path).unwrap_or(false) {
+                    cache::touch(&run_logs_path);
+                    let result = fs::File::open(&run_logs_path).and_then(|file| {
+                        run_logs::read_step_logs(io::BufReader::new(file), &to_extract, save_log)
+                    });
+                    match result {
+                        Ok(found) => found,
+                        Err(e) => fail!("zip error: {e}\n in path {run_logs_path}"),
                     }
-                    Err(e) => {
-                        let _ = fs::remove_dir_all(extract_dir);
-                        fail!("tar failed to run: {e}")
+                } else if cli.offline {
+                    missing.push(Missing::RunLogs {
+                        run_id: id,
+                        title: title.clone(),
+                    });
+                    to_extract.clear();
+                    HashSet::new()
+                } else {
+                    let keep = cli.keep_run_logs.then_some(Path::new(&run_logs_path));
+                    match run_logs::download_step_logs(id, &to_extract, keep, save_log) {
+                        Ok(found) => found,
+                        Err(e) => fail!("github error: {e}"),
+                    }
+                };
+                for (job, step) in to_extract {
+                    let job_id = job.id;
+                    if !found.contains(&job_id) {
+                        eprintln!(
+                            "no log for step {} ({}) of job {job_id} in the logs for run {id}",
+                            step.number, step.name
+                        );
+                        continue;
                     }
-                    Ok(_) => {}
+                    let step_log_path = format!("{extract_dir}/{job_id}.txt");
+                    let log = match cache::open(&step_log_path) {
+                        Ok(Some(log)) => log,
+                        Ok(None) => fail!("step log missing from the cache: {step_log_path}"),
+                        Err(e) => fail!("filesystem error: {e}\n in path {step_log_path}"),
+                    };
+                    let processed = match extract::process_step_log(log, job, step, &rules) {
+                        Ok(processed) => processed,
+                        Err(e) => fail!("filesystem error: {e}\n in path {step_log_path}"),
+                    };
+                    let processed_path = format!("{extract_dir}/{job_id}.processed.json");
+                    if let Err(e) = extract::save(&processed_path, &processed) {
+                        fail!("filesystem error: {e}\n in path {processed_path}");
+                    }
+                    processed_jobs.push((job, step_log_path, processed));
                 }
             }
-            todo!("either finish writing this or delete it");
         } else {
             // Download only the failed logs.
             // Smaller but not separated by step.
             // Should be fine though, trimming it seems to work.
-            for job in jobs.jobs {
+            for job in &jobs.jobs {
                 // Skip success and bors.
                 if job.conclusion != Conclusion::Failure || job.name == "bors build finished" {
                     continue;
                 }
                 let job_id = job.id;
                 let job_log_path = format!("{jobs_logs_dir}/{job_id}.txt");
-                let mut log = if !fs::exists(&job_log_path).unwrap_or(false) {
-                    let result = GithubApi::new(&format!(
-                        "repos/rust-lang-ci/rust/actions/jobs/{job_id}/logs"
-                    ))
-                    .run();
-                    let log = match result {
-                        Ok(output) => output,
-                        Err(e) => fail!("github error: {e}"),
-                    };
-
-                    if let Err(e) = fs::write(&job_log_path, &log) {
-                        fail!("filesystem error: {e}\n in path {job_log_path}");
-                    }
-                    log
+                let processed_path = format!("{jobs_logs_dir}/{job_id}.processed.json");
+                let cached = match extract::load(&processed_path, &rules) {
+                    Ok(cached) => cached,
+                    Err(e) => fail!("filesystem error: {e}\n in path {processed_path}"),
+                };
+                let processed = if let Some(processed) = cached {
+                    // The report links to the log, so it's still in use.
+                    cache::touch(&job_log_path);
+                    processed
                 } else {
-                    match fs::read_to_string(&job_log_path) {
+                    // Logs can be huge so they're only ever read from the
+                    // cache a piece at a time, even just after downloading.
+                    let cached = match cache::open(&job_log_path) {
+                        Ok(cached) => cached,
                         Err(e) => fail!("filesystem error: {e}\n in path {job_log_path}"),
-                        Ok(log) => log,
-                    }
-                };
-
-                trim_log(&mut log);
-                let short_log = short_log(&log);
-                // Parse the PR id from the title
-                let pr_id: u64 = if let Some(text_id) = title
-                    .strip_prefix("Auto merge of #")
-                    .and_then(|s| s.split_once(" ").map(|s| s.0))
-                {
-                    match text_id.parse() {
-                        Ok(id) => id,
-                        Err(e) => fail!("PR id not found: {e}"),
+                    };
+                    let log = if let Some(log) = cached {
+                        log
+                    } else if cli.offline {
+                        missing.push(Missing::Log {
+                            run_id: id,
+                            job_id,
+                            job_name: job.name.clone(),
+                        });
+                        continue;
+                    } else {
+                        let result = GithubApi::new(&format!(
+                            "repos/rust-lang-ci/rust/actions/jobs/{job_id}/logs"
+                        ))
+                        .download(Path::new(&job_log_path));
+                        if let Err(e) = result {
+                            fail!("github error: {e}");
+                        }
+                        match cache::open(&job_log_path) {
+                            Ok(Some(log)) => log,
+                            Ok(None) => fail!("job log missing from the cache: {job_log_path}"),
+                            Err(e) => fail!("filesystem error: {e}\n in path {job_log_path}"),
+                        }
+                    };
+                    let processed = match extract::process_job_log(log, job, &rules) {
+                        Ok(processed) => processed,
+                        Err(e) => fail!("filesystem error: {e}\n in path {job_log_path}"),
+                    };
+                    if let Err(e) = extract::save(&processed_path, &processed) {
+                        fail!("filesystem error: {e}\n in path {processed_path}");
                     }
-                } else {
-                    fail!("PR id not found");
+                    processed
                 };
-                let error_line = error_line(&short_log).map(String::from);
-                fails.fails.push(Fail {
-                    title: title.clone(),
-                    job_name: job.name,
-                    job_id: job.id,
-                    url: job.html_url,
-                    time: job.started_at,
-                    //log,
-                    short_log,
-                    error_line,
-                    pr_id,
-                });
+                processed_jobs.push((job, job_log_path, processed));
             }
         }
+
+        for (job, raw_log, processed) in processed_jobs {
+            let extract::Processed {
+                short_log,
+                elided,
+                error,
+                error_offset,
+                step,
+                groups,
+                timing,
+                tests,
+                panic,
+                linker,
+                native,
+                infra,
+                network,
+                ..
+            } = processed;
+            // Parse the PR id from the title
+            let pr_id: u64 = if let Some(text_id) = title
+                .strip_prefix("Auto merge of #")
+                .and_then(|s| s.split_once(" ").map(|s| s.0))
+            {
+                match text_id.parse() {
+                    Ok(id) => id,
+                    Err(e) => fail!("PR id not found: {e}"),
+                }
+            } else {
+                fail!("PR id not found");
+            };
+            // The JSON gets the plain text but the colours are kept for the HTML.
+            let styled_log = Log::parse(&short_log);
+            fails.fails.push(Fail {
+                title: title.clone(),
+                job_name: job.name.clone(),
+                job_id: job.id,
+                url: job.html_url.clone(),
+                time: job.started_at.clone(),
+                //log,
+                short_log: styled_log.text(),
+                styled_log,
+                elided,
+                error_line: error.as_ref().map(|e| e.headline.clone()),
+                error,
+                raw_log,
+                error_offset,
+                pr_id,
+                step_number: step.as_ref().map(|s| s.number),
+                step_name: step.map(|s| s.name),
+                groups,
+                timing,
+                tests,
+                panic,
+                linker,
+                native,
+                infra,
+                network,
+            });
+        }
+    }
+
+    if !missing.is_empty() {
+        println!("Missing from the cache ({}):", missing.len());
+        for missing in &missing {
+            println!("{missing}");
+        }
     }
+    fails.missing = missing;
 
     let report_dir = format!("report/{start}..{end}/");
     if let Err(e) = fs::create_dir_all(&report_dir) {
@@ -308,180 +469,653 @@ fn main() -> ExitCode {
     ExitCode::SUCCESS
 }
 
-fn trim_log(log: &mut String) {
-    // remove time lines
-    let mut strip_log = String::new();
-    for line in log.lines() {
-        let line = if let Some((head, tail)) = line.split_once(" ") {
-            // FIXME: Exactly specify the expected format
-            if head.parse::<Timestamp>().is_ok() {
-                tail
-            } else {
-                line
-            }
-        } else {
-            line
-        };
-        strip_log.push_str(line);
-        strip_log.push('\n');
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Fails {
+    start: String,
+    end: String,
+    success: u64,
+    fail: u64,
+    cancelled: u64,
+    fails: Vec<Fail>,
+    /// What couldn't be found in the cache when running offline.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    missing: Vec<Missing>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Missing {
+    /// The list of workflow runs for the date range.
+    Runs { range: String },
+    /// The list of jobs for a workflow run.
+    Jobs { run_id: u64, title: String },
+    /// The full logs archive for a workflow run.
+    RunLogs { run_id: u64, title: String },
+    /// The log for a failed job.
+    Log {
+        run_id: u64,
+        job_id: u64,
+        job_name: String,
+    },
+}
+
+impl fmt::Display for Missing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Runs { range } => write!(f, "workflow runs for {range}"),
+            Self::Jobs { run_id, title } => write!(f, "jobs for run {run_id}: {title}"),
+            Self::RunLogs { run_id, title } => write!(f, "logs for run {run_id}: {title}"),
+            Self::Log {
+                run_id,
+                job_id,
+                job_name,
+            } => write!(f, "log for job {job_id} ({job_name}) in run {run_id}"),
+        }
     }
-    *log = strip_log;
+}
 
-    // remove ansi escapes
-    let mut strip_log = Vec::new();
-    let mut mode = AnsiMode::Text;
-    for &b in log.as_bytes() {
-        if mode.update(b).is_text() {
-            strip_log.push(b);
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Fail {
+    title: String,
+    time: String,
+    job_name: String,
+    job_id: u64,
+    url: String,
+    //log: String,
+    short_log: String,
+    /// The short log with its colours, for the HTML.
+    #[serde(skip)]
+    styled_log: Log,
+    /// The lines of the short log that are shown shortened.
+    #[serde(default)]
+    elided: Vec<elide::Elided>,
+    /// The headline of `error`.
+    error_line: Option<String>,
+    #[serde(default)]
+    error: Option<extract::ErrorSummary>,
+    /// Where the job's log is in the cache, relative to where this was run.
+    #[serde(default)]
+    raw_log: String,
+    /// Where the error line is in the raw log, in bytes.
+    #[serde(default)]
+    error_offset: Option<u64>,
+    pr_id: u64,
+    /// The step that failed, if it could be found.
+    #[serde(default)]
+    step_number: Option<u64>,
+    #[serde(default)]
+    step_name: Option<String>,
+    /// How long each group of the log took, in the order they started.
+    #[serde(default)]
+    groups: Vec<timing::GroupTiming>,
+    /// When the job failed, if the log had timestamps.
+    #[serde(default)]
+    timing: Option<timing::Timing>,
+    /// The tests that failed, if any.
+    #[serde(default)]
+    tests: Vec<libtest::TestFailure>,
+    /// The panic that caused the failure, if any.
+    #[serde(default)]
+    panic: Option<panic::Panic>,
+    /// Why linking failed, if it did.
+    #[serde(default)]
+    linker: Option<linker::LinkerFailure>,
+    /// Why a C or C++ build failed, if one did.
+    #[serde(default)]
+    native: Option<native::NativeFailure>,
+    /// Set if the failure was the machine's fault rather than the code's.
+    #[serde(default)]
+    infra: Option<infra::Category>,
+    /// Set if the network failed.
+    #[serde(default)]
+    network: Option<network::NetworkFailure>,
+}
+
+#[derive(Parser)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+    /// The start date, in YY-mm-dd format. E.g. 2025-04-01.
+    start_date: Option<String>,
+    /// The end date, in YY-mm-dd format. E.g. 2025-05-01.
+    end_date: Option<String>,
+    /// Only use cached data. Anything missing from the cache is listed in the report.
+    #[arg(long)]
+    offline: bool,
+    /// Cache the whole logs archive for each run, not just the failed steps.
+    /// Only used with the `download_full_logs` feature.
+    #[arg(long)]
+    keep_run_logs: bool,
+    /// A TOML file of extraction rules to use on top of the built-in ones.
+    #[arg(long)]
+    rules: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Remove old or least recently used entries from the cache.
+    Gc(GcArgs),
+    /// Inspect or manage the cache.
+    Cache(CacheArgs),
+}
+
+#[derive(clap::Args)]
+struct CacheArgs {
+    #[command(subcommand)]
+    command: CacheCommands,
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Check every cache entry against its checksum.
+    Verify {
+        /// Delete corrupt entries so they're downloaded again next time.
+        #[arg(long)]
+        delete: bool,
+    },
+    /// Package the cached run lists, job lists and logs for a date range into a bundle.
+    Export {
+        /// The date range of a cached run list, e.g. 2025-04-01..2025-05-01.
+        range: String,
+        /// Where to write the bundle.
+        #[arg(short, long, default_value = "bundle.tar.zst")]
+        output: PathBuf,
+    },
+    /// Merge a bundle into the local cache. Newer local entries are kept.
+    Import {
+        /// The bundle to import.
+        bundle: PathBuf,
+    },
+}
+
+fn cache_command(command: CacheCommands) -> ExitCode {
+    let result = match command {
+        CacheCommands::Verify { delete } => cache::verify(delete),
+        CacheCommands::Export { range, output } => {
+            if range.contains(['/', '\\']) {
+                fail!("invalid range: {range}");
+            }
+            bundle::export(&range, &output)
         }
+        CacheCommands::Import { bundle } => bundle::import(&bundle),
+    };
+    if let Err(e) = result {
+        fail!("cache error: {e}");
     }
-    *log = String::from_utf8(strip_log).unwrap();
+    ExitCode::SUCCESS
+}
+
+#[derive(clap::Args)]
+struct GcArgs {
+    /// Remove logs and job lists that haven't been used in this many days.
+    #[arg(long, value_name = "DAYS")]
+    older_than: Option<u64>,
+    /// Evict the least recently used entries until the cache fits in this size. E.g. 500M or 2G.
+    #[arg(long, value_name = "SIZE", value_parser = cache::parse_size)]
+    max_size: Option<u64>,
+    /// Also remove run lists. These are small so are kept by default.
+    #[arg(long)]
+    include_runs: bool,
+    /// Print what would be removed without removing anything.
+    #[arg(long)]
+    dry_run: bool,
+}
 
-    // remove the cleanup step from logs
-    if let Some(pos) = log.rfind("\nPost job cleanup.\n") {
-        log.truncate(pos + 1);
+fn gc(args: &GcArgs) -> ExitCode {
+    if args.older_than.is_none() && args.max_size.is_none() {
+        fail!("gc needs at least one of --older-than or --max-size");
+    }
+    let older_than = match args.older_than.map(|days| days.checked_mul(DAYS)) {
+        Some(None) => fail!("--older-than is too large"),
+        Some(Some(seconds)) => Some(Duration::from_secs(seconds)),
+        None => None,
+    };
+    let options = cache::GcOptions {
+        older_than,
+        max_size: args.max_size,
+        include_runs: args.include_runs,
+        dry_run: args.dry_run,
+    };
+    if let Err(e) = cache::gc(&options) {
+        fail!("filesystem error: {e}");
     }
+    ExitCode::SUCCESS
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        // Logs can contain binary junk, which isn't allowed in HTML.
+        .replace(
+            |c: char| c.is_control() && c != '\t' && c != '\n',
+            "\u{FFFD}",
+        )
+}
+
+/// Renders a log with its groups as collapsible sections.
+///
+/// The `elided` lines are collapsed too, behind their summary.
+fn log_html(log: &Log, elided: &[elide::Elided]) -> String {
+    let mut html = String::new();
+    render_lines(log, 0..log.len(), &log.groups(), elided, &mut html);
+    html
+}
 
-    // Get only the last group.
-    if let Some(pos) = log.rfind("\n##[group") {
-        log.replace_range(..pos + 1, "");
+fn render_lines(
+    log: &Log,
+    lines: Range<usize>,
+    groups: &[Group],
+    elided: &[elide::Elided],
+    html: &mut String,
+) {
+    let mut pos = lines.start;
+    for group in groups {
+        render_plain_lines(log, pos..group.lines.start, elided, html);
+        html.push_str("<details open><summary>");
+        html.push_str(&escape_html(&group.name));
+        html.push_str("</summary>");
+        render_lines(
+            log,
+            group.lines.start + 1..group.lines.end,
+            &group.children,
+            elided,
+            html,
+        );
+        html.push_str("</details>");
+        pos = group.lines.end;
     }
+    render_plain_lines(log, pos..lines.end, elided, html);
 }
 
-fn short_log(log: &str) -> String {
-    let group = if log.starts_with("##[group") {
-        log.lines().next()
-    } else {
-        None
-    };
-    if let Some(pos) = log.find("\nfailures:\n") {
-        log[pos..].into()
-    } else if let Some(pos) = log.find("\n##[error]The runner has received a shutdown signal.") {
-        // No point printing the full logs if the run was essentially cancelled by outside forces.
-        log[pos..].into()
-    } else if group.is_some_and(|g| g.starts_with("##[group]Building LLVM for ")) {
-        let mut short = group.unwrap().to_string();
-        if let Some(pos) = log.find("\nFAILED: ") {
-            short.push_str(&log[pos..]);
-            short
-        } else {
-            // we couldn't find a failure message but we truncate the output anyway
-            // because otherwise it can be gigantic.
-            short.push('\n');
-            let log = tail_lines(&log[short.len()..], 50);
-            short.push_str(log);
-            short
+fn render_plain_lines(
+    log: &Log,
+    mut lines: Range<usize>,
+    elided: &[elide::Elided],
+    html: &mut String,
+) {
+    while let Some(i) = lines.next() {
+        if let Some(elided) = elided.iter().find(|e| e.lines.start == i) {
+            let text: Vec<&str> = log.lines[elided.lines.clone()]
+                .iter()
+                .map(|l| l.text.as_str())
+                .collect();
+            html.push_str(&format!(
+                "<details class=\"collapsed\"><summary>{}</summary>{}</details>",
+                escape_html(&elided.summary),
+                escape_html(&text.join("\n"))
+            ));
+            lines.start = elided.lines.end;
+            continue;
         }
-    } else {
-        // limit the logs to some reasonable number of lines.
-        let short_log = tail_lines(log, 500);
-        if short_log.len() != log.len() {
-            if let Some(group) = group {
-                String::from(group) + short_log
+        let line = &log.lines[i];
+        let mut text = String::new();
+        for (part, style) in line.styled_parts() {
+            let part = escape_html(part);
+            if style.is_default() {
+                text.push_str(&part);
             } else {
-                short_log.into()
+                text.push_str(&format!("<span style=\"{}\">{part}</span>", style.css()));
+            }
+        }
+        match line.kind {
+            // The end of a group is shown by the end of the `<details>`.
+            LineKind::GroupEnd => continue,
+            LineKind::Error => html.push_str(&format!("<span class=\"log-error\">{text}</span>")),
+            LineKind::Warning => {
+                html.push_str(&format!("<span class=\"log-warning\">{text}</span>"))
+            }
+            LineKind::Text | LineKind::GroupStart => html.push_str(&text),
+        }
+        html.push('\n');
+    }
+}
+
+/// E.g. "Failed after 2h13m in group X".
+fn timing_html(timing: &timing::Timing) -> String {
+    let mut html = format!(
+        "<p>Failed after {}",
+        timing::format_duration(timing.failed_after)
+    );
+    if let Some(group) = &timing.group {
+        html.push_str(&format!(" in group <code>{}</code>", escape_html(group)));
+        if let Some(duration) = timing.group_duration {
+            html.push_str(&format!(
+                " ({} into the group)",
+                timing::format_duration(duration)
+            ));
+        }
+    }
+    if timing.near_time_limit {
+        html.push_str(" <strong>near the job time limit</strong>");
+    }
+    html.push_str("</p>");
+    html
+}
+
+/// How many of the slowest groups are shown.
+const SLOWEST_GROUPS: usize = 10;
+
+/// The groups that took longest, slowest first.
+fn groups_html(groups: &[timing::GroupTiming]) -> String {
+    if groups.is_empty() {
+        return String::new();
+    }
+    let mut slowest: Vec<_> = groups.iter().collect();
+    slowest.sort_by_key(|g| std::cmp::Reverse(g.duration));
+    let mut html = String::from(
+        "<details class=\"groups\"><summary>Slowest groups</summary><table><thead><tr><th>Group</th><th>Started</th><th>Took</th></tr></thead><tbody>",
+    );
+    for group in slowest.into_iter().take(SLOWEST_GROUPS) {
+        html.push_str(&format!(
+            "<tr><td><code>{}</code></td><td>{}</td><td>{}</td></tr>",
+            escape_html(&group.name),
+            group.start,
+            timing::format_duration(group.duration)
+        ));
+    }
+    html.push_str("</tbody></table></details>");
+    html
+}
+
+/// The failed tests of a job, with why each one failed.
+fn tests_html(tests: &[libtest::TestFailure]) -> String {
+    if tests.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<ul class=\"tests\">");
+    for test in tests {
+        html.push_str(&format!("<li><code>{}</code>", escape_html(&test.name)));
+        // compiletest's own panics don't say anything about the test.
+        if let Some(compiletest) = &test.compiletest {
+            if let Some(kind) = compiletest.kind {
+                html.push_str(&format!(": {}", kind.description()));
+            }
+            if let Some(revision) = &compiletest.revision {
+                let revision = escape_html(revision);
+                html.push_str(&format!(" in revision <code>{revision}</code>"));
+            }
+            if let Some(command) = &compiletest.command {
+                html.push_str(&format!(
+                    "<details><summary>Command</summary><pre>{}</pre></details>",
+                    escape_html(command)
+                ));
+            }
+            for diff in &compiletest.diffs {
+                html.push_str(&diff_html(diff));
             }
         } else {
-            log.into()
+            if let Some(location) = &test.location {
+                let location = escape_html(location);
+                html.push_str(&format!(" panicked at <code>{location}</code>"));
+            }
+            if let Some(message) = &test.panic_message {
+                html.push_str(&format!("<pre>{}</pre>", escape_html(message)));
+            }
         }
+        html.push_str("</li>");
     }
+    html.push_str("</ul>");
+    html
 }
 
-fn tail_lines(log: &str, lines: usize) -> &str {
-    let mut pos = log.len();
-    for _ in 0..lines {
-        if pos == 0 {
-            break;
-        } else if let Some(new_pos) = log[..pos - 1].rfind("\n") {
-            pos = new_pos + 1
+/// A diff of a UI test's output, coloured like `git diff`.
+fn diff_html(diff: &compiletest::Diff) -> String {
+    let mut html = format!(
+        "<details open><summary>Diff of {}</summary><pre class=\"diff\">",
+        escape_html(&diff.output)
+    );
+    for (change, line) in diff.lines() {
+        let class = match change {
+            Change::Removed => "diff-removed",
+            Change::Added => "diff-added",
+            Change::Hunk => "diff-hunk",
+            Change::Unchanged => "",
+        };
+        let line = escape_html(line);
+        if class.is_empty() {
+            html.push_str(&line);
         } else {
-            pos = 0;
+            html.push_str(&format!("<span class=\"{class}\">{line}</span>"));
         }
+        html.push('\n');
     }
-    &log[pos..]
+    html.push_str("</pre></details>");
+    html
 }
 
-fn error_line(log: &str) -> Option<&str> {
-    for line in log.lines() {
-        if line == "##[error]Process completed with exit code 1." {
-            // This is the "something went wrong" of errors.
-            continue;
-        } else if line.starts_with("error: ")
-            || line.starts_with("error[")
-            || line.starts_with("rustc exited with signal:")
-            || line.starts_with("##[error]")
-            || line.starts_with("TypeError:")
-            || line.starts_with("dyld[")
-        {
-            if !line.starts_with("error: test failed, to rerun")
-                && line != "error: rmake recipe failed to complete"
-            {
-                return Some(line);
+/// Where and why a job panicked, with the top of the backtrace.
+fn panic_html(panic: &panic::Panic) -> String {
+    let mut html = format!(
+        "<div class=\"panic\"><p>Thread <code>{}</code> panicked",
+        escape_html(&panic.thread)
+    );
+    if let Some(location) = &panic.location {
+        html.push_str(&format!(" at <code>{}</code>", escape_html(location)));
+    }
+    html.push_str("</p>");
+    if let Some(message) = &panic.message {
+        html.push_str(&format!("<pre>{}</pre>", escape_html(message)));
+    }
+    if !panic.frames.is_empty() {
+        html.push_str("<ol class=\"frames\">");
+        for frame in &panic.frames {
+            html.push_str(&format!(
+                "<li><code>{}</code>",
+                escape_html(&frame.function)
+            ));
+            if let Some(location) = &frame.location {
+                html.push_str(&format!(" at <code>{}</code>", escape_html(location)));
             }
+            html.push_str("</li>");
         }
+        html.push_str("</ol>");
     }
+    html.push_str("</div>");
+    html
+}
 
-    // We didn't find any errors. Let's look for some lesser candidates.
-    let mut report_next = false;
-    let mut previous = "";
-    for line in log.lines() {
-        if report_next {
-            // Fixme: report both lines.
-            if line.trim().is_empty()
-                || line == "explicit panic"
-                || line == "assertion `left == right` failed"
-            {
-                return Some(previous);
-            } else {
-                return Some(line);
-            }
-        } else if line.starts_with("ERROR: ")
-            || line.starts_with("error in revision ")
-            || line.starts_with("fatal: ")
-        {
-            return Some(line);
-        } else if line.starts_with("thread '")
-            && line.contains("' panicked at ")
-            && line.ends_with(":")
-        {
-            previous = line;
-            report_next = true;
+/// The headline of an error, which can be expanded to show its context.
+fn error_html(error: &extract::ErrorSummary) -> String {
+    let headline = format!("<code>{}</code>", escape_html(&error.headline));
+    if error.context.len() <= 1 {
+        return format!("<pre>{headline}</pre>");
+    }
+    format!(
+        "<details><summary>{headline}</summary><pre>{}</pre></details>",
+        escape_html(&error.context.join("\n"))
+    )
+}
+
+/// What the linker couldn't find, with the command line collapsed.
+fn linker_html(linker: &linker::LinkerFailure) -> String {
+    let mut html = String::from("<div class=\"linker\"><p>Linking");
+    if let Some(name) = &linker.linker {
+        html.push_str(&format!(" with <code>{}</code>", escape_html(name)));
+    }
+    html.push_str(" failed</p>");
+    for (what, names) in [
+        ("Undefined symbols", &linker.symbols),
+        ("Objects", &linker.objects),
+    ] {
+        if names.is_empty() {
+            continue;
         }
+        let names: Vec<String> = names
+            .iter()
+            .map(|n| format!("<code>{}</code>", escape_html(n)))
+            .collect();
+        html.push_str(&format!("<p>{what}: {}</p>", names.join(", ")));
+    }
+    if let Some(command) = &linker.command {
+        html.push_str(&format!(
+            "<details><summary>Linker command ({} characters)</summary><pre>{}</pre></details>",
+            command.chars().count(),
+            escape_html(command)
+        ));
     }
+    html.push_str("</div>");
+    html
+}
 
-    None
+/// What failed to compile and why.
+fn native_html(native: &native::NativeFailure) -> String {
+    let mut html = String::from("<div class=\"native\"><p>");
+    match (&native.translation_unit, &native.target) {
+        (Some(file), _) => html.push_str(&format!(
+            "Failed to compile <code>{}</code>",
+            escape_html(file)
+        )),
+        (None, Some(target)) => html.push_str(&format!(
+            "Failed to build <code>{}</code>",
+            escape_html(target)
+        )),
+        // Only cmake fails without building anything.
+        (None, None) => html.push_str("Configuring with CMake failed"),
+    }
+    if let Some(cause) = native.cause {
+        html.push_str(&format!(": <strong>{}</strong>", cause.description()));
+    }
+    html.push_str("</p>");
+    if let Some(error) = &native.error {
+        let location = match error.line {
+            Some(line) => format!("{}:{line}", error.file),
+            None => error.file.clone(),
+        };
+        html.push_str(&format!(
+            "<p><code>{}</code>: {}</p>",
+            escape_html(&location),
+            escape_html(&error.message)
+        ));
+    }
+    html.push_str("</div>");
+    html
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Fails {
-    start: String,
-    end: String,
-    success: u64,
-    fail: u64,
-    cancelled: u64,
-    fails: Vec<Fail>,
+/// Links to the short logs of jobs.
+fn job_links(jobs: &[u64]) -> String {
+    let links: Vec<String> = jobs
+        .iter()
+        .map(|id| format!("<a href=\"#job-{id}\">{id}</a>"))
+        .collect();
+    links.join(" ")
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Fail {
-    title: String,
-    time: String,
-    job_name: String,
-    job_id: u64,
-    url: String,
-    //log: String,
-    short_log: String,
-    error_line: Option<String>,
-    pr_id: u64,
+/// How many jobs failed because of the machine rather than the code.
+fn infra_stats_html(fails: &[Fail]) -> String {
+    let mut counts: BTreeMap<infra::Category, usize> = BTreeMap::new();
+    for category in fails.iter().filter_map(|f| f.infra) {
+        *counts.entry(category).or_default() += 1;
+    }
+    if counts.is_empty() {
+        return String::new();
+    }
+    let infra: usize = counts.values().sum();
+    let counts: Vec<String> = counts
+        .into_iter()
+        .map(|(category, count)| format!("{count} {}", category.description()))
+        .collect();
+    format!(
+        "<p><strong>{infra}</strong> of the {} failed jobs were infrastructure failures ({})</p>",
+        fails.len(),
+        counts.join(", ")
+    )
 }
 
-#[derive(Parser)]
-#[command(version, about, long_about = None)]
-struct Cli {
-    /// The start date, in YY-mm-dd format. E.g. 2025-04-01.
-    start_date: Option<String>,
-    /// The end date, in YY-mm-dd format. E.g. 2025-05-01.
-    end_date: Option<String>,
+/// How many jobs failed because of each host, most first.
+fn network_stats_html(fails: &[Fail]) -> String {
+    let mut hosts: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+    for fail in fails {
+        if let Some(network) = &fail.network {
+            let host = network.host.as_deref().unwrap_or("unknown host");
+            hosts.entry(host).or_default().push(fail.job_id);
+        }
+    }
+    if hosts.is_empty() {
+        return String::new();
+    }
+    let failures: usize = hosts.values().map(Vec::len).sum();
+    let mut hosts: Vec<_> = hosts.into_iter().collect();
+    hosts.sort_by_key(|(_, jobs)| std::cmp::Reverse(jobs.len()));
+    let mut html = format!(
+        "<details><summary>{failures} jobs failed because of the network</summary><table class=\"network-stats\"><thead><tr><th>Host</th><th>Failures</th><th>Jobs</th></tr></thead><tbody>"
+    );
+    for (host, jobs) in hosts {
+        html.push_str(&format!(
+            "<tr><td><code>{}</code></td><td>{}</td><td>{}</td></tr>",
+            escape_html(host),
+            jobs.len(),
+            job_links(&jobs)
+        ));
+    }
+    html.push_str("</tbody></table></details>");
+    html
+}
+
+/// Jobs grouped by where and why they panicked, biggest group first.
+fn panic_stats_html(fails: &[Fail]) -> String {
+    let mut groups: BTreeMap<String, (&panic::Panic, Vec<u64>)> = BTreeMap::new();
+    for fail in fails {
+        if let Some(panic) = &fail.panic {
+            groups
+                .entry(panic.signature())
+                .or_insert((panic, Vec::new()))
+                .1
+                .push(fail.job_id);
+        }
+    }
+    if groups.is_empty() {
+        return String::new();
+    }
+    let mut groups: Vec<_> = groups.into_values().collect();
+    groups.sort_by_key(|(_, jobs)| std::cmp::Reverse(jobs.len()));
+    let mut html = format!(
+        "<details><summary>{} different panics</summary><table class=\"panic-stats\"><thead><tr><th>Location</th><th>Message</th><th>Failures</th><th>Jobs</th></tr></thead><tbody>",
+        groups.len()
+    );
+    for (panic, jobs) in groups {
+        let location = escape_html(panic.location.as_deref().unwrap_or(""));
+        let message = panic.message.as_deref().unwrap_or("");
+        let message = escape_html(message.lines().next().unwrap_or(""));
+        html.push_str(&format!(
+            "<tr><td><code>{location}</code></td><td><code>{message}</code></td><td>{}</td><td>{}</td></tr>",
+            jobs.len(),
+            job_links(&jobs)
+        ));
+    }
+    html.push_str("</tbody></table></details>");
+    html
+}
+
+/// How often each test failed, most often first.
+fn test_stats_html(fails: &[Fail]) -> String {
+    let mut jobs: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+    for fail in fails {
+        for test in &fail.tests {
+            jobs.entry(&test.name).or_default().push(fail.job_id);
+        }
+    }
+    if jobs.is_empty() {
+        return String::new();
+    }
+    let mut jobs: Vec<_> = jobs.into_iter().collect();
+    // Stable, so tests that failed as often stay in name order.
+    jobs.sort_by_key(|(_, jobs)| std::cmp::Reverse(jobs.len()));
+    let mut html = format!(
+        "<details><summary>{} tests failed</summary><table class=\"test-stats\"><thead><tr><th>Test</th><th>Failures</th><th>Jobs</th></tr></thead><tbody>",
+        jobs.len()
+    );
+    for (name, jobs) in jobs {
+        html.push_str(&format!(
+            "<tr><td><code>{}</code></td><td>{}</td><td>{}</td></tr>",
+            escape_html(name),
+            jobs.len(),
+            job_links(&jobs)
+        ));
+    }
+    html.push_str("</tbody></table></details>");
+    html
 }
 
 // FIXME: do this properly
@@ -493,9 +1127,14 @@ fn make_html(fails: &Fails) -> String {
         fail,
         cancelled,
         fails,
+        missing,
     } = fails;
     let total = success + fail;
-    let percent = (fail * 100) / total;
+    let percent = (fail * 100).checked_div(total).unwrap_or(0);
+    let test_stats = test_stats_html(fails);
+    let panic_stats = panic_stats_html(fails);
+    let infra_stats = infra_stats_html(fails);
+    let network_stats = network_stats_html(fails);
     let mut html = String::new();
     html.push_str(
         r#"<!DOCTYPE html>
@@ -513,6 +1152,10 @@ fn make_html(fails: &Fails) -> String {
         <h1>Rustc CI failures {start} to {end}</h1>
         <article id=\"stats\">
             <p><strong>{fail}</strong> out of <strong>{total}</strong> runs failed ({percent}%) plus {cancelled} workflows were cancelled</p>
+            {infra_stats}
+            {network_stats}
+            {test_stats}
+            {panic_stats}
         </article>
         "
     ));
@@ -520,7 +1163,7 @@ fn make_html(fails: &Fails) -> String {
     let mut summary = String::from(
         "<section id = \"summary\">
         <h2>Summary</h2>
-        <form id=\"filter\"><input placeholder=\"filter summary by log text\" type=\"search\"><input type=\"submit\" value=\"Filter\"></form>
+        <form id=\"filter\"><input placeholder=\"filter summary by log text\" type=\"search\"><label><input type=\"checkbox\" name=\"hide-infra\"> Hide infrastructure and network failures</label><input type=\"submit\" value=\"Filter\"></form>
         <table><thead><tr><th>Time (UTC)</th><th>PR</th><th>Job Name</th><th>Short Log</th><th>Error Message</th></tr></thead>
         <tbody>
         ",
@@ -533,22 +1176,89 @@ fn make_html(fails: &Fails) -> String {
             job_name,
             job_id,
             url,
-            short_log,
-            error_line,
+            styled_log,
+            elided,
+            error,
+            raw_log,
+            error_offset,
             pr_id,
+            step_number,
+            step_name,
+            groups,
+            timing,
+            tests,
+            panic,
+            linker,
+            native,
+            infra,
+            network,
+            ..
         } = fail;
-        let mut short_log = short_log.replace("&", "&amp;");
-        short_log = short_log.replace("<", "&lt;");
-        short_log = short_log.replace(">", "&gt;");
-        let error_line = error_line.as_deref().unwrap_or("");
+        let short_log = log_html(styled_log, elided);
+        let step = match (step_number, step_name) {
+            (Some(number), Some(name)) => format!("<p>Step {number}: {}</p>", escape_html(name)),
+            _ => String::new(),
+        };
+        let timing = timing.as_ref().map_or(String::new(), timing_html);
+        let groups = groups_html(groups);
+        let tests = tests_html(tests);
+        let panic = panic.as_ref().map_or(String::new(), panic_html);
+        let linker = linker.as_ref().map_or(String::new(), linker_html);
+        let native = native.as_ref().map_or(String::new(), native_html);
+        // The report is two directories down from where it was run.
+        let raw_log = match error_offset {
+            Some(offset) => {
+                format!("<p><a href=\"../../{raw_log}\">Raw log</a> (error at byte {offset})</p>")
+            }
+            None => format!("<p><a href=\"../../{raw_log}\">Raw log</a></p>"),
+        };
+        let (confidence, error) = match error {
+            Some(error) => (
+                format!(
+                    " title=\"{} ({} confidence)\" data-confidence=\"{}\"",
+                    escape_html(&error.rule),
+                    error.confidence.description(),
+                    error.confidence.description()
+                ),
+                error_html(error),
+            ),
+            None => Default::default(),
+        };
+        let (infra_attr, infra_tag, infra) = match infra {
+            Some(category) => {
+                let description = category.description();
+                (
+                    format!(" data-infra=\"{description}\""),
+                    format!("<span class=\"infra-tag\">{description}</span>"),
+                    format!("<p class=\"infra\">Infrastructure failure: {description}</p>"),
+                )
+            }
+            None => Default::default(),
+        };
+        let (network_attr, network_tag, network) = match network {
+            Some(network) => {
+                let host = network.host.as_deref().unwrap_or("unknown host");
+                let description = format!("{} ({host})", network.operation.description());
+                let description = escape_html(&description);
+                (
+                    format!(" data-network=\"{}\"", escape_html(host)),
+                    format!("<span class=\"infra-tag\">network: {description}</span>"),
+                    format!(
+                        "<p class=\"infra\">Network failure: {description}</p><pre>{}</pre>",
+                        escape_html(&network.line)
+                    ),
+                )
+            }
+            None => Default::default(),
+        };
         summary.push_str(&format!(
             "
-            <tr data-job-id=\"{job_id}\">
+            <tr data-job-id=\"{job_id}\"{infra_attr}{network_attr}>
             <td>{time}</td>
             <td><a href=\"https://github.com/rust-lang/rust/pull/{pr_id}\">#{pr_id}</a></td>
             <td>{job_name}</td>
             <td><a href=\"#job-{job_id}\">log</a></td>
-            <td class=\"error_msg\"><pre><code>{error_line}</code></pre></td>
+            <td class=\"error_msg\"{confidence}>{infra_tag}{network_tag}{error}</td>
             </tr>
             ",
         ));
@@ -557,14 +1267,35 @@ fn make_html(fails: &Fails) -> String {
             <article id=\"job-{job_id}\" class=\"failure\">
                 <h3><a href=\"{url}\">{title}</a></h3>
                 <p>{job_name}</p>
+                {step}
                 <p>{time}</p>
-                <pre class=\"log\"><code>{short_log}</code></pre>
+                {timing}
+                {groups}
+                {raw_log}
+                {infra}
+                {network}
+                {panic}
+                {linker}
+                {native}
+                {tests}
+                <div class=\"log\">{short_log}</div>
             </article> 
             "
         ));
     }
     summary.push_str("</tbody></table></section>");
     logs.push_str("</section>");
+    if !missing.is_empty() {
+        html.push_str(&format!(
+            "<section id=\"missing\"><h2>Missing from the cache ({})</h2>\n<p>This report was made offline so these were skipped.</p>\n<ul>\n",
+            missing.len()
+        ));
+        for missing in missing {
+            let missing = escape_html(&missing.to_string());
+            html.push_str(&format!("<li>{missing}</li>\n"));
+        }
+        html.push_str("</ul></section>");
+    }
     html.push_str(&summary);
     html.push_str(&logs);
 
@@ -572,7 +1303,20 @@ fn make_html(fails: &Fails) -> String {
         r##"
         <script src="script.js"></script>
         <style>
-        .log { overflow: auto; border: 1px solid black; padding: 1em; background-color: #eee; }
+        .log { overflow: auto; border: 1px solid black; padding: 1em; background-color: #eee; white-space: pre; font-family: monospace; }
+        .log summary { font-weight: bold; cursor: pointer; }
+        .log-error { color: #c00; font-weight: bold; }
+        .log-warning { color: #a60; }
+        .tests pre, .panic pre, .linker pre { white-space: pre-wrap; margin: 0.25em 0; }
+        .log details.collapsed { white-space: pre-wrap; }
+        .log details.collapsed summary { font-weight: normal; }
+        .infra-tag { font-size: 12px; background-color: #ddd; border-radius: 4px; padding: 0 4px; }
+        .infra { font-weight: bold; color: #555; }
+        .infra + pre { white-space: pre-wrap; }
+        .diff { background-color: #f6f8fa; padding: 0.5em; }
+        .diff-removed { background-color: #ffebe9; color: #82071e; }
+        .diff-added { background-color: #dafbe1; color: #116329; }
+        .diff-hunk { color: #0550ae; }
         table { border-collapse: collapse; }
         thead tr { border-bottom: 2px solid white; }
         th { position: sticky; top: 0; background-color: white; }
@@ -581,6 +1325,8 @@ fn make_html(fails: &Fails) -> String {
         tr:nth-child(even) { background: #eee; }
         .error_msg { font-size: 12px; }
         .error_msg pre { white-space: pre-wrap; word-wrap: break-word; }
+        .error_msg summary { cursor: pointer; white-space: pre-wrap; word-wrap: break-word; }
+        .error_msg[data-confidence=low] summary, .error_msg[data-confidence=low] > pre { color: #777; }
         #filter { display: flex; }
         #filter input[type=search] { flex-grow: 1; }
         #summary { min-height: 100vh; }
@@ -589,11 +1335,12 @@ fn make_html(fails: &Fails) -> String {
         document.querySelector("#filter").addEventListener("submit", event => {
             event.preventDefault();
             const search = event.target.querySelector("input[type=\"search\"]").value;
+            const hideInfra = event.target.querySelector("input[name=\"hide-infra\"]").checked;
             let count = 0;
             let hidden = 0;
             document.querySelectorAll("#summary tbody tr").forEach(tr => {
                 const job_id = tr.dataset.jobId;
-                if (document.querySelector(`#job-${job_id} code`).textContent.includes(search)) {
+                if (!(hideInfra && (tr.dataset.infra || tr.dataset.network)) && document.querySelector(`#job-${job_id} .log`).textContent.includes(search)) {
                     tr.removeAttribute("style");
                     if (count % 2 == 0) {
                         tr.style["background-color"] = "white";
@@ -613,6 +1360,9 @@ fn make_html(fails: &Fails) -> String {
                 title.textContent = `Summary (showing ${count} results)`
             }
         });
+        document.querySelector("#filter input[name=\"hide-infra\"]").addEventListener("change", () => {
+            document.querySelector("#filter").requestSubmit();
+        });
         document.querySelectorAll("#summary th").forEach(th => {
             th.addEventListener("click", event => {
                 const tr = event.target;
@@ -0,0 +1,395 @@
+//! Reading C and C++ build failures, mostly from building LLVM.
+//!
+//! ninja prints the output of a command that failed after a `FAILED:` line
+//! and the command itself:
+//!
+//! ```text
+//! [1234/5678] Building CXX object lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o
+//! FAILED: lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o
+//! /usr/bin/c++ -DFOO ... -o lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o -c /checkout/src/llvm-project/llvm/lib/Foo/Bar.cpp
+//! /checkout/src/llvm-project/llvm/lib/Foo/Bar.cpp:10:5: error: use of undeclared identifier 'x'
+//! 1 error generated.
+//! ninja: build stopped: subcommand failed.
+//! ```
+//!
+//! The errors are in the formats of clang and gcc (`file:line:column: error: ...`)
+//! or MSVC (`file(line): error C1234: ...`). cmake can fail before there's
+//! anything to build, with `CMake Error at file:line (command):`.
+
+use crate::log::Line;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// How many lines after the error are kept in the short log.
+const CONTEXT_LINES: usize = 10;
+/// The most lines of a failed command's output that are looked at.
+const MAX_OUTPUT_LINES: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NativeFailure {
+    /// What ninja was building, e.g. `lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o`.
+    pub target: Option<String>,
+    /// The source file being compiled.
+    pub translation_unit: Option<String>,
+    /// The first error.
+    pub error: Option<Diagnostic>,
+    pub cause: Option<Cause>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl NativeFailure {
+    /// Whether there's an error or a cause to show for the failure, rather
+    /// than just the command that failed.
+    pub fn is_explained(&self) -> bool {
+        self.error.is_some() || self.cause.is_some()
+    }
+}
+
+/// Why a compiler failed, when it's not because of an error in the code.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Cause {
+    OutOfMemory,
+    CompilerCrash,
+}
+
+impl Cause {
+    /// What compilers say when they run out of memory or crash. Being killed
+    /// is nearly always the kernel's OOM killer.
+    const MESSAGES: [(&str, Cause); 13] = [
+        ("Killed signal terminated program", Cause::OutOfMemory),
+        ("unable to execute command: Killed", Cause::OutOfMemory),
+        ("virtual memory exhausted", Cause::OutOfMemory),
+        ("Cannot allocate memory", Cause::OutOfMemory),
+        ("LLVM ERROR: out of memory", Cause::OutOfMemory),
+        ("std::bad_alloc", Cause::OutOfMemory),
+        // MSVC: `fatal error C1060: compiler is out of heap space`
+        ("error C1060", Cause::OutOfMemory),
+        ("internal compiler error", Cause::CompilerCrash),
+        ("PLEASE submit a bug report", Cause::CompilerCrash),
+        (
+            "frontend command failed due to signal",
+            Cause::CompilerCrash,
+        ),
+        ("Segmentation fault", Cause::CompilerCrash),
+        ("Stack dump:", Cause::CompilerCrash),
+        // MSVC: `fatal error C1001: Internal compiler error.`
+        ("error C1001", Cause::CompilerCrash),
+    ];
+
+    fn of(text: &str) -> Option<Self> {
+        Self::MESSAGES
+            .iter()
+            .find(|(message, _)| text.contains(message))
+            .map(|&(_, cause)| cause)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::OutOfMemory => "out of memory",
+            Self::CompilerCrash => "the compiler crashed",
+        }
+    }
+}
+
+/// A native build failure and where it is in the log.
+pub struct Found {
+    pub failure: NativeFailure,
+    /// The lines about the failure, for the short log.
+    pub lines: Range<usize>,
+    /// The line that best describes the failure.
+    pub error_line: usize,
+}
+
+/// Finds the first native build failure in the lines. A failed command with
+/// an error is preferred over one without.
+pub fn find(lines: &[Line]) -> Option<Found> {
+    let mut first = None;
+    let mut start = 0;
+    while let Some(i) = lines[start..].iter().position(|l| starts_failure(&l.text)) {
+        let found = parse(&lines[start + i..]);
+        let found = Found {
+            lines: found.lines.start + start + i..found.lines.end + start + i,
+            error_line: found.error_line + start + i,
+            ..found
+        };
+        if found.failure.is_explained() {
+            return Some(found);
+        }
+        start = found.lines.end.max(start + i + 1);
+        first.get_or_insert(found);
+    }
+    first
+}
+
+/// Whether a group builds C or C++, in which case any failure in it is a
+/// native one.
+pub fn is_native_group(name: &str) -> bool {
+    name.starts_with("Building LLVM for ")
+}
+
+fn starts_failure(text: &str) -> bool {
+    text.starts_with("FAILED: ") || text.starts_with("CMake Error at ")
+}
+
+/// Whether the line is after the output of the failed command.
+fn ends_failure(text: &str) -> bool {
+    starts_failure(text)
+        || text.starts_with("ninja: ")
+        || text.starts_with("-- Configuring incomplete")
+        // ninja's progress, e.g. `[1234/5678] Building CXX object ...`
+        || text
+            .strip_prefix('[')
+            .and_then(|s| s.split_once(']'))
+            .is_some_and(|(progress, _)| {
+                progress.contains('/') && progress.bytes().all(|b| b.is_ascii_digit() || b == b'/')
+            })
+}
+
+/// Parses the failure that starts on the first line.
+fn parse(lines: &[Line]) -> Found {
+    let first = lines[0].text.as_str();
+    let end = lines
+        .iter()
+        .skip(1)
+        .take(MAX_OUTPUT_LINES)
+        .position(|l| ends_failure(&l.text))
+        .map_or(lines.len().min(MAX_OUTPUT_LINES + 1), |i| i + 1);
+    let mut failure = NativeFailure::default();
+    let mut error_line = None;
+    let mut cause_line = None;
+    if let Some(target) = first.strip_prefix("FAILED: ") {
+        let target = target.trim();
+        failure.target = Some(target.into());
+        failure.translation_unit = lines
+            .get(1)
+            .and_then(|l| compiled_file(&l.text))
+            .or_else(|| source_of(target));
+    } else {
+        failure.error = cmake_error(&lines[..end]);
+        error_line = failure.error.is_some().then_some(0);
+    }
+    for (i, line) in lines[..end].iter().enumerate().skip(1) {
+        if failure.error.is_none()
+            && let Some(error) = diagnostic(&line.text)
+        {
+            failure.error = Some(error);
+            error_line = Some(i);
+        }
+        if failure.cause.is_none()
+            && let Some(cause) = Cause::of(&line.text)
+        {
+            failure.cause = Some(cause);
+            cause_line = Some(i);
+        }
+    }
+    // Keep what's after the error, or the crash, for context.
+    let last = error_line.max(cause_line).unwrap_or(end);
+    Found {
+        failure,
+        lines: 0..end.min(last + CONTEXT_LINES + 1),
+        error_line: error_line.or(cause_line).unwrap_or(0),
+    }
+}
+
+/// The file compiled by a compiler's command line, which is the argument
+/// after `-c`, or `/c` for MSVC.
+fn compiled_file(command: &str) -> Option<String> {
+    let mut args = command.split_whitespace();
+    args.by_ref().find(|&a| a == "-c" || a == "/c")?;
+    let file = args.next()?.trim_matches('"');
+    (!file.starts_with('-')).then(|| file.into())
+}
+
+/// The source file of a cmake object file, e.g. `lib/Foo/Bar.cpp` for
+/// `lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o`.
+fn source_of(target: &str) -> Option<String> {
+    let object = target
+        .strip_suffix(".o")
+        .or_else(|| target.strip_suffix(".obj"))?;
+    let (dir, rest) = object.split_once("CMakeFiles/")?;
+    let (_, file) = rest.split_once(".dir/")?;
+    Some(format!("{dir}{file}"))
+}
+
+/// Parses a clang, gcc or MSVC error.
+fn diagnostic(text: &str) -> Option<Diagnostic> {
+    let text = text.trim();
+    // MSVC: `C:\foo\bar.cpp(10,5): error C2065: 'x': undeclared identifier`
+    for marker in ["): error ", "): fatal error "] {
+        if let Some((location, message)) = text.split_once(marker)
+            && let Some((file, position)) = location.rsplit_once('(')
+        {
+            let line = position.split(',').next()?.parse().ok();
+            return Some(Diagnostic {
+                file: file.into(),
+                line,
+                message: message.into(),
+            });
+        }
+    }
+    // clang and gcc: `/foo/bar.cpp:10:5: error: use of undeclared identifier 'x'`
+    for marker in [": error: ", ": fatal error: "] {
+        if let Some((location, message)) = text.split_once(marker) {
+            // Windows paths have colons, so the numbers are taken from the end.
+            let mut file = location;
+            let mut numbers = Vec::new();
+            while numbers.len() < 2
+                && let Some((rest, number)) = file.rsplit_once(':')
+                && is_number(number)
+            {
+                numbers.push(number);
+                file = rest;
+            }
+            // Errors from the compiler driver, e.g. `clang: error: ...`,
+            // aren't about a file.
+            let Some(line) = numbers.last() else {
+                continue;
+            };
+            return Some(Diagnostic {
+                file: file.into(),
+                line: line.parse().ok(),
+                message: message.into(),
+            });
+        }
+    }
+    None
+}
+
+/// Parses a `CMake Error at CMakeLists.txt:10 (message):` line. The message
+/// is on the lines after it.
+fn cmake_error(lines: &[Line]) -> Option<Diagnostic> {
+    let location = lines[0].text.strip_prefix("CMake Error at ")?;
+    let location = location.split(" (").next()?.trim_end_matches(':');
+    let (file, line) = match location.rsplit_once(':') {
+        Some((file, line)) if is_number(line) => (file, line.parse().ok()),
+        _ => (location, None),
+    };
+    let message = lines[1..]
+        .iter()
+        .map(|l| l.text.trim())
+        .find(|t| !t.is_empty())
+        .unwrap_or("");
+    Some(Diagnostic {
+        file: file.into(),
+        line,
+        message: message.into(),
+    })
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::Log;
+
+    fn find_in(log: &str) -> Found {
+        find(&Log::parse(log).lines).expect("a native failure")
+    }
+
+    #[test]
+    fn ninja_and_clang() {
+        let found = find_in(
+            "\
+[1234/5678] Building CXX object lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o
+FAILED: lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o
+/usr/bin/c++ -DFOO -o lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o -c /checkout/src/llvm-project/llvm/lib/Foo/Bar.cpp
+/checkout/src/llvm-project/llvm/lib/Foo/Bar.cpp:10:5: error: use of undeclared identifier 'x'
+1 error generated.
+ninja: build stopped: subcommand failed.
+",
+        );
+        assert_eq!(found.lines, 1..5);
+        assert_eq!(found.error_line, 3);
+        let failure = found.failure;
+        assert_eq!(
+            failure.target.as_deref(),
+            Some("lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o")
+        );
+        assert_eq!(
+            failure.translation_unit.as_deref(),
+            Some("/checkout/src/llvm-project/llvm/lib/Foo/Bar.cpp")
+        );
+        let error = failure.error.unwrap();
+        assert_eq!(
+            error.file,
+            "/checkout/src/llvm-project/llvm/lib/Foo/Bar.cpp"
+        );
+        assert_eq!(error.line, Some(10));
+        assert_eq!(error.message, "use of undeclared identifier 'x'");
+        assert_eq!(failure.cause, None);
+    }
+
+    #[test]
+    fn msvc() {
+        let found = find_in(
+            "\
+FAILED: lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.obj
+C:\\foo\\bar.cpp(10,5): error C2065: 'x': undeclared identifier
+",
+        );
+        let failure = found.failure;
+        assert_eq!(failure.translation_unit.as_deref(), Some("lib/Foo/Bar.cpp"));
+        let error = failure.error.unwrap();
+        assert_eq!(error.file, "C:\\foo\\bar.cpp");
+        assert_eq!(error.line, Some(10));
+        assert_eq!(error.message, "C2065: 'x': undeclared identifier");
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let found = find_in(
+            "\
+FAILED: lib/Foo/CMakeFiles/LLVMFoo.dir/Bar.cpp.o
+/usr/bin/c++ -c /checkout/src/llvm-project/llvm/lib/Foo/Bar.cpp
+c++: fatal error: Killed signal terminated program cc1plus
+compilation terminated.
+ninja: build stopped: subcommand failed.
+",
+        );
+        assert_eq!(found.error_line, 2);
+        assert!(found.failure.error.is_none());
+        assert_eq!(found.failure.cause, Some(Cause::OutOfMemory));
+    }
+
+    #[test]
+    fn cmake() {
+        let found = find_in(
+            "\
+-- The C compiler identification is GNU 9.4.0
+CMake Error at CMakeLists.txt:10 (message):
+  LLVM requires a newer compiler
+-- Configuring incomplete, errors occurred!
+",
+        );
+        assert_eq!(found.lines, 1..3);
+        let error = found.failure.error.unwrap();
+        assert_eq!(error.file, "CMakeLists.txt");
+        assert_eq!(error.line, Some(10));
+        assert_eq!(error.message, "LLVM requires a newer compiler");
+    }
+
+    #[test]
+    fn prefers_an_explained_failure() {
+        let found = find_in(
+            "\
+FAILED: a.o
+FAILED: b.o
+b.cpp:1:1: error: oops
+",
+        );
+        assert_eq!(found.failure.target.as_deref(), Some("b.o"));
+        assert!(found.failure.is_explained());
+        let found = find_in("FAILED: a.o\nsomething went wrong\n");
+        assert!(!found.failure.is_explained());
+    }
+}
@@ -0,0 +1,183 @@
+//! Spotting failures caused by the network, which are nearly always spurious.
+
+use crate::log::Line;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NetworkFailure {
+    /// The host that couldn't be reached, if the log says.
+    pub host: Option<String>,
+    pub operation: Operation,
+    /// The line the failure was spotted on.
+    pub line: String,
+}
+
+/// What was being done when the network failed.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// Looking up a host name.
+    Dns,
+    Download,
+    /// Cargo fetching the registry or a dependency.
+    CargoFetch,
+    GitClone,
+    GitSubmodule,
+}
+
+impl Operation {
+    /// What's in the log for each operation. If a line has more than one then
+    /// the first in this list wins, as e.g. curl can fail because of DNS.
+    const MESSAGES: [(&str, Operation); 14] = [
+        ("Could not resolve host", Operation::Dns),
+        ("Temporary failure in name resolution", Operation::Dns),
+        ("Name or service not known", Operation::Dns),
+        ("failed to lookup address information", Operation::Dns),
+        ("into submodule path", Operation::GitSubmodule),
+        // git retries once first.
+        ("a second time, aborting", Operation::GitSubmodule),
+        ("fatal: unable to access '", Operation::GitClone),
+        (
+            "fatal: the remote end hung up unexpectedly",
+            Operation::GitClone,
+        ),
+        ("fatal: early EOF", Operation::GitClone),
+        ("error: RPC failed", Operation::GitClone),
+        // e.g. `curl: (56) Recv failure: Connection reset by peer`
+        ("curl: (", Operation::Download),
+        ("failed to download", Operation::Download),
+        ("failed to fetch `", Operation::CargoFetch),
+        ("failed to update registry", Operation::CargoFetch),
+    ];
+
+    fn of(text: &str) -> Option<Self> {
+        Self::MESSAGES
+            .iter()
+            .find(|(message, _)| text.contains(message))
+            .map(|&(_, operation)| operation)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Dns => "DNS lookup",
+            Self::Download => "download",
+            Self::CargoFetch => "cargo fetch",
+            Self::GitClone => "git clone",
+            Self::GitSubmodule => "git submodule update",
+        }
+    }
+}
+
+/// How many lines after the failure are looked at for the host, if the
+/// failure's own line doesn't say.
+const HOST_LINES: usize = 5;
+
+/// Finds the first network failure in the part of the log the failure is in.
+pub fn find(lines: &[Line]) -> Option<NetworkFailure> {
+    // Things that are retried are warned about first, and may well work the
+    // next time.
+    let mut lines = lines.iter().filter(|l| !l.text.starts_with("warning: "));
+    let (line, operation) = lines
+        .by_ref()
+        .find_map(|l| Some((l, Operation::of(&l.text)?)))?;
+    // The host is often only mentioned by the next few lines, e.g. in the
+    // cause cargo gives for a failure.
+    let host = host(&line.text).or_else(|| {
+        lines
+            .take(HOST_LINES)
+            .filter(|l| Operation::of(&l.text).is_some())
+            .find_map(|l| host(&l.text))
+    });
+    Some(NetworkFailure {
+        host: host.map(String::from),
+        operation,
+        line: line.text.clone(),
+    })
+}
+
+/// Finds the host a line is about.
+fn host(text: &str) -> Option<&str> {
+    const BEFORE_HOST: [&str; 4] = ["resolve host: ", "connect to ", "resolve host ", "://"];
+    BEFORE_HOST.iter().find_map(|before| {
+        let (_, rest) = text.split_once(before)?;
+        let host = rest
+            .split(|c: char| c.is_whitespace() || "/'`\"():,".contains(c))
+            .next()?;
+        // Drop the user of e.g. `git@github.com`.
+        let host = host.rsplit('@').next()?;
+        host.contains('.').then_some(host)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::Log;
+
+    fn find_in(log: &str) -> Option<NetworkFailure> {
+        find(&Log::parse(log).lines)
+    }
+
+    #[test]
+    fn dns() {
+        let found = find_in("curl: (6) Could not resolve host: ci-mirrors.rust-lang.org").unwrap();
+        assert_eq!(found.operation, Operation::Dns);
+        assert_eq!(found.host.as_deref(), Some("ci-mirrors.rust-lang.org"));
+    }
+
+    #[test]
+    fn tls() {
+        let found = find_in(
+            "fatal: unable to access 'https://github.com/rust-lang/llvm-project/': gnutls_handshake() failed: The TLS connection was non-properly terminated.",
+        )
+        .unwrap();
+        assert_eq!(found.operation, Operation::GitClone);
+        assert_eq!(found.host.as_deref(), Some("github.com"));
+    }
+
+    #[test]
+    fn timeout() {
+        let found = find_in(
+            "curl: (28) Failed to connect to static.rust-lang.org port 443 after 130000 ms: Connection timed out",
+        )
+        .unwrap();
+        assert_eq!(found.operation, Operation::Download);
+        assert_eq!(found.host.as_deref(), Some("static.rust-lang.org"));
+    }
+
+    #[test]
+    fn host_from_the_cause() {
+        let found = find_in(
+            "\
+warning: spurious network error (3 tries remaining): [6] Couldn't resolve host name
+error: failed to download from `https://static.crates.io/crates/libc/libc-0.2.0.crate`
+Caused by:
+  [6] Couldn't resolve host name (Could not resolve host: static.crates.io)
+",
+        )
+        .unwrap();
+        assert_eq!(found.operation, Operation::Download);
+        assert_eq!(found.host.as_deref(), Some("static.crates.io"));
+        assert!(found.line.starts_with("error: failed to download"));
+    }
+
+    #[test]
+    fn host_only_from_the_next_lines() {
+        let mut log = String::from("error: failed to update registry\n");
+        for _ in 0..HOST_LINES {
+            log.push_str("something else\n");
+        }
+        log.push_str("curl: (6) Could not resolve host: example.com\n");
+        let found = find_in(&log).unwrap();
+        assert_eq!(found.operation, Operation::CargoFetch);
+        assert_eq!(found.host, None);
+    }
+
+    #[test]
+    fn retries_are_not_failures() {
+        assert!(
+            find_in("warning: spurious network error (2 tries remaining): curl: (56) Recv failure")
+                .is_none()
+        );
+    }
+}
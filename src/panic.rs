@@ -0,0 +1,321 @@
+//! Reading panics, and their backtraces, out of a log.
+//!
+//! ```text
+//! thread 'main' panicked at src/main.rs:2:5:
+//! explicit panic
+//! stack backtrace:
+//!    0: rust_begin_unwind
+//!              at /rustc/.../library/std/src/panicking.rs:665:5
+//!    1: core::panicking::panic_fmt
+//!              at /rustc/.../library/core/src/panicking.rs:74:14
+//!    2: example::main
+//!              at ./src/main.rs:2:5
+//! note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::iter::Peekable;
+
+/// The most lines of a panic message that are kept.
+const MAX_MESSAGE_LINES: usize = 50;
+/// The most frames of a backtrace that are kept.
+const MAX_FRAMES: usize = 5;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Panic {
+    pub thread: String,
+    /// Where it panicked, e.g. `src/lib.rs:10:5`.
+    pub location: Option<String>,
+    pub message: Option<String>,
+    /// The top of the backtrace, if there was one, without the frames in
+    /// the standard library.
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Frame {
+    pub function: String,
+    pub location: Option<String>,
+}
+
+impl Panic {
+    /// Identifies panics that are probably the same bug.
+    pub fn signature(&self) -> String {
+        let location = self.location.as_deref().unwrap_or("");
+        let message = self.message.as_deref().unwrap_or("");
+        format!("{location}\n{}", message.lines().next().unwrap_or(""))
+    }
+
+    /// compiletest panics to fail a test, which says nothing about why.
+    fn is_compiletest(&self) -> bool {
+        self.location
+            .as_deref()
+            .is_some_and(|l| l.starts_with("src/tools/compiletest/"))
+    }
+}
+
+/// Finds the first panic in the lines, other than compiletest's own.
+pub fn find<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<Panic> {
+    let mut lines = lines.into_iter().peekable();
+    loop {
+        let panic = next(&mut lines)?;
+        if !panic.is_compiletest() {
+            return Some(panic);
+        }
+    }
+}
+
+/// Finds the next panic.
+fn next<'a>(lines: &mut Peekable<impl Iterator<Item = &'a str>>) -> Option<Panic> {
+    let (thread, at) = lines.by_ref().find_map(panic_line)?;
+    // Since Rust 1.73 the message is on the lines after the location.
+    let (location, message) = if let Some(location) = at.strip_suffix(':') {
+        let mut message = Vec::new();
+        while let Some(line) = lines.next_if(|l| !ends_message(l)) {
+            if message.len() < MAX_MESSAGE_LINES {
+                message.push(line);
+            }
+        }
+        while message.last().is_some_and(|l| l.trim().is_empty()) {
+            message.pop();
+        }
+        let message = (!message.is_empty()).then(|| message.join("\n"));
+        (Some(location), message)
+    } else if let Some((message, location)) =
+        // Before that it was `'message', location`.
+        at.strip_prefix('\'').and_then(|s| s.rsplit_once("', "))
+    {
+        (Some(location), Some(message.to_string()))
+    } else {
+        (Some(at), None)
+    };
+    let frames = if lines.next_if(|&l| l == "stack backtrace:").is_some() {
+        frames(lines)
+    } else {
+        Vec::new()
+    };
+    Some(Panic {
+        thread: thread.into(),
+        location: location.map(String::from),
+        message,
+        frames,
+    })
+}
+
+/// The thread and what comes after `panicked at ` of a
+/// `thread 'main' panicked at ...` line.
+fn panic_line(line: &str) -> Option<(&str, &str)> {
+    line.strip_prefix("thread '")?.split_once("' panicked at ")
+}
+
+fn ends_message(line: &str) -> bool {
+    line.starts_with("note: ") || line == "stack backtrace:" || panic_line(line).is_some()
+}
+
+/// Reads the frames of a backtrace, which are either
+///
+/// ```text
+///    2: example::main
+///              at ./src/main.rs:2:5
+/// ```
+///
+/// or, with `RUST_BACKTRACE=full`,
+///
+/// ```text
+///    2:     0x55f0c4e0b1a4 - example::main::h0123456789abcdef
+///                                at ./src/main.rs:2:5
+/// ```
+fn frames<'a>(lines: &mut Peekable<impl Iterator<Item = &'a str>>) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    while let Some(function) = lines.peek().and_then(|l| frame_function(l)) {
+        lines.next();
+        let location = lines
+            .next_if(|l| l.trim_start().starts_with("at "))
+            .map(|l| l.trim_start()["at ".len()..].to_string());
+        if frames.len() < MAX_FRAMES && !is_std(&function) {
+            frames.push(Frame { function, location });
+        }
+    }
+    frames
+}
+
+fn frame_function(line: &str) -> Option<String> {
+    let (number, function) = line.trim_start().split_once(": ")?;
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let function = function.trim_start();
+    // Skip the address.
+    let function = match function.split_once(" - ") {
+        Some((address, function)) if address.starts_with("0x") => function,
+        _ => function,
+    };
+    // And the hash at the end of the symbol.
+    let function = match function.rsplit_once("::h") {
+        Some((function, hash))
+            if hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()) =>
+        {
+            function
+        }
+        _ => function,
+    };
+    Some(function.into())
+}
+
+/// Whether the frame is part of the machinery of panicking, starting
+/// threads or running tests, rather than the code that panicked.
+fn is_std(function: &str) -> bool {
+    const PREFIXES: [&str; 12] = [
+        "std::",
+        "core::",
+        "alloc::",
+        "panic_unwind::",
+        "__rust",
+        "__libc_start",
+        // libtest's, rather than those of any crate called `test`.
+        "test::run_test",
+        "test::__rust_begin_short_backtrace",
+        "test::types::",
+        "test::console::",
+        "test::test_main",
+        "test::assert_test_result",
+    ];
+    // Symbols of the C runtime and the OS, which are matched exactly so
+    // functions like `clone_from` aren't skipped.
+    const SYMBOLS: [&str; 12] = [
+        "main",
+        "<unknown>",
+        "rust_begin_unwind",
+        "rust_panic",
+        "_start",
+        "clone",
+        "clone3",
+        "__clone",
+        "__clone3",
+        "start_thread",
+        "BaseThreadInitThunk",
+        "RtlUserThreadStart",
+    ];
+    if SYMBOLS.contains(&function) {
+        return true;
+    }
+    // Trait impls are written like `<core::...>`.
+    let function = function.trim_start_matches('<');
+    PREFIXES.iter().any(|p| function.starts_with(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_in(log: &str) -> Panic {
+        find(log.lines()).expect("there's a panic")
+    }
+
+    #[test]
+    fn message_after_location() {
+        let panic = find_in(
+            "\
+thread 'main' panicked at src/main.rs:2:5:
+explicit panic
+stack backtrace:
+   0: rust_begin_unwind
+             at /rustc/0123456789abcdef/library/std/src/panicking.rs:665:5
+   1: core::panicking::panic_fmt
+             at /rustc/0123456789abcdef/library/core/src/panicking.rs:74:14
+   2: example::main
+             at ./src/main.rs:2:5
+note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.",
+        );
+        assert_eq!(panic.thread, "main");
+        assert_eq!(panic.location.as_deref(), Some("src/main.rs:2:5"));
+        assert_eq!(panic.message.as_deref(), Some("explicit panic"));
+        assert_eq!(panic.frames.len(), 1);
+        assert_eq!(panic.frames[0].function, "example::main");
+        assert_eq!(
+            panic.frames[0].location.as_deref(),
+            Some("./src/main.rs:2:5")
+        );
+    }
+
+    #[test]
+    fn multi_line_message() {
+        let panic = find_in(
+            "\
+thread 'tests::foo' panicked at src/lib.rs:10:5:
+assertion `left == right` failed
+  left: 1
+ right: 2
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace",
+        );
+        assert_eq!(
+            panic.message.as_deref(),
+            Some("assertion `left == right` failed\n  left: 1\n right: 2")
+        );
+        assert!(panic.frames.is_empty());
+    }
+
+    #[test]
+    fn message_before_location() {
+        let panic = find_in("thread 'main' panicked at 'explicit panic', src/main.rs:2:5");
+        assert_eq!(panic.location.as_deref(), Some("src/main.rs:2:5"));
+        assert_eq!(panic.message.as_deref(), Some("explicit panic"));
+    }
+
+    #[test]
+    fn full_backtrace() {
+        let panic = find_in(
+            "\
+thread 'main' panicked at src/main.rs:2:5:
+explicit panic
+stack backtrace:
+   0:     0x55f0c4e0a1b2 - std::backtrace_rs::backtrace::libunwind::trace::h0123456789abcdef
+                               at /rustc/0123456789abcdef/library/std/src/../../backtrace/src/backtrace/libunwind.rs:116:5
+   1:     0x55f0c4e0b1a4 - example::main::h0123456789abcdef
+                               at ./src/main.rs:2:5
+   2:     0x55f0c4e0b1c0 - core::ops::function::FnOnce::call_once::h0123456789abcdef
+   3:     0x7f0c4e0b1d00 - __libc_start_main
+   4:     0x55f0c4e0b0e0 - _start
+   5:                0x0 - <unknown>",
+        );
+        assert_eq!(panic.frames.len(), 1);
+        assert_eq!(panic.frames[0].function, "example::main");
+        assert_eq!(
+            panic.frames[0].location.as_deref(),
+            Some("./src/main.rs:2:5")
+        );
+    }
+
+    #[test]
+    fn skips_compiletest() {
+        let panic = find_in(
+            "\
+thread '[ui] tests/ui/foo.rs' panicked at src/tools/compiletest/src/runtest.rs:100:5:
+fatal error
+thread 'main' panicked at src/main.rs:2:5:
+explicit panic",
+        );
+        assert_eq!(panic.location.as_deref(), Some("src/main.rs:2:5"));
+    }
+
+    #[test]
+    fn keeps_user_frames() {
+        for function in [
+            "clone_from",
+            "cloned",
+            "test::helper",
+            "mainly::run",
+            "_start_server",
+        ] {
+            assert!(!is_std(function), "{function}");
+        }
+        for function in [
+            "clone",
+            "__clone3",
+            "test::run_test_in_process",
+            "std::rt::lang_start",
+        ] {
+            assert!(is_std(function), "{function}");
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! The rules used to pick out the interesting parts of a log.
+//!
+//! The built-in rules are in `rules.toml`, which also describes the format. A
+//! user's rules file is applied on top of them.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::cache;
+
+const DEFAULT_RULES: &str = include_str!("rules.toml");
+
+/// The contents of a rules file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    /// Start from nothing rather than the built-in rules.
+    #[serde(default)]
+    replace_defaults: bool,
+    #[serde(default)]
+    error_line: Vec<ErrorLineRule>,
+    #[serde(default)]
+    short_log: Vec<ShortLogRule>,
+}
+
+/// The rules in effect, sorted by priority.
+#[derive(Serialize)]
+pub struct Rules {
+    pub error_line: Vec<ErrorLineRule>,
+    pub short_log: Vec<ShortLogRule>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorLineRule {
+    pub name: String,
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(rename = "match")]
+    pub matches: Vec<Pattern>,
+    #[serde(default)]
+    pub exclude: Vec<Pattern>,
+    #[serde(default)]
+    pub report: Report,
+    #[serde(default)]
+    pub unless_next: Vec<Pattern>,
+    #[serde(default)]
+    pub confidence: Confidence,
+}
+
+/// Which line an error line rule reports.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Report {
+    /// The line that matched.
+    #[default]
+    Line,
+    /// The line after the one that matched.
+    Next,
+}
+
+/// How sure an error line rule is that what it matches is the cause of the
+/// failure.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl Confidence {
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ShortLogRule {
+    pub name: String,
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub priority: i32,
+    pub group: Option<Pattern>,
+    pub from: Option<Pattern>,
+    #[serde(default)]
+    pub group_header: bool,
+    pub tail: Option<usize>,
+}
+
+/// Matches the text of a line. Every condition that's given has to match.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Pattern {
+    pub equals: Option<String>,
+    pub starts_with: Option<String>,
+    pub contains: Option<String>,
+    pub ends_with: Option<String>,
+    /// Whether the line is empty or only whitespace.
+    #[serde(default)]
+    pub blank: bool,
+}
+
+impl Pattern {
+    pub fn matches(&self, text: &str) -> bool {
+        self.equals.as_ref().is_none_or(|s| text == s)
+            && self
+                .starts_with
+                .as_ref()
+                .is_none_or(|s| text.starts_with(s))
+            && self.contains.as_ref().is_none_or(|s| text.contains(s))
+            && self.ends_with.as_ref().is_none_or(|s| text.ends_with(s))
+            && (!self.blank || text.trim().is_empty())
+    }
+}
+
+impl ErrorLineRule {
+    pub fn matches(&self, text: &str) -> bool {
+        self.matches.iter().any(|p| p.matches(text))
+            && !self.exclude.iter().any(|p| p.matches(text))
+    }
+}
+
+/// Anything with a name that can be overridden by a user's rule.
+trait Named {
+    fn name(&self) -> &str;
+    fn disabled(&self) -> bool;
+    fn priority(&self) -> i32;
+}
+
+impl Named for ErrorLineRule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn disabled(&self) -> bool {
+        self.disabled
+    }
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+}
+
+impl Named for ShortLogRule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn disabled(&self) -> bool {
+        self.disabled
+    }
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+}
+
+/// Applies the user's rules on top of the base ones.
+fn merge<T: Named>(mut base: Vec<T>, user: Vec<T>) -> Vec<T> {
+    for rule in user {
+        match base.iter().position(|r| r.name() == rule.name()) {
+            Some(i) => base[i] = rule,
+            None => base.push(rule),
+        }
+    }
+    base.retain(|r| !r.disabled());
+    // Stable, so rules with the same priority stay in the order they were given.
+    base.sort_by_key(|r| r.priority());
+    base
+}
+
+impl Rules {
+    /// The built-in rules, with the rules in the file at `path`, if any,
+    /// applied on top.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let defaults: RulesFile =
+            toml::from_str(DEFAULT_RULES).expect("the built-in rules are valid");
+        let user = match path {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("{e}\n in path {}", path.display()))?;
+                toml::from_str(&text).map_err(|e| format!("{e}\n in path {}", path.display()))?
+            }
+            None => RulesFile::default(),
+        };
+        let (error_line, short_log) = if user.replace_defaults {
+            (Vec::new(), Vec::new())
+        } else {
+            (defaults.error_line, defaults.short_log)
+        };
+        Ok(Self {
+            error_line: merge(error_line, user.error_line),
+            short_log: merge(short_log, user.short_log),
+        })
+    }
+
+    /// Identifies the rules, so results from different rules aren't mixed up.
+    pub fn hash(&self) -> u64 {
+        let json = serde_json::to_vec(self).expect("rules can always be serialized");
+        cache::hash(&json)
+    }
+}
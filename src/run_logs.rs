@@ -0,0 +1,436 @@
+//! Reading the full logs archive for a workflow run.
+//!
+//! The archive has a directory per job holding a file per step, named
+//! `{step_number}_{step_name}.txt`. GitHub mangles some characters in job
+//! names so directories are matched loosely.
+//!
+//! Archives can be hundreds of megabytes so they're read as a stream, straight
+//! from the download, using the local file headers. Only the wanted entries are
+//! decompressed, and then only a piece at a time, and reading stops as soon as
+//! they've all been found.
+
+use crate::cache;
+use crate::github::{GhError, GithubApi, Job, Step};
+use flate2::Crc;
+use flate2::bufread::DeflateDecoder;
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06064b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const USES_DATA_DESCRIPTOR: u16 = 1 << 3;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const ZIP64_EXTRA: u16 = 0x0001;
+
+/// Whether an entry in the archive is the log for the given job and step.
+pub fn is_step_log(entry_name: &str, job: &Job, step: &Step) -> bool {
+    let Some((dir, file)) = entry_name.rsplit_once('/') else {
+        return false;
+    };
+    let prefix = format!("{}_", step.number);
+    file.starts_with(&prefix) && file.ends_with(".txt") && loose_name(dir) == loose_name(&job.name)
+}
+
+fn loose_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Reads the logs of the wanted steps out of a zip archive, passing each to
+/// `on_log` along with its job. Returns the ids of the jobs that were found.
+///
+/// Stops reading as soon as every wanted log has been found, so the reader
+/// may be left part way through the archive.
+pub fn read_step_logs<R: BufRead>(
+    mut reader: R,
+    wanted: &[(&Job, &Step)],
+    mut on_log: impl FnMut(&Job, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<HashSet<u64>> {
+    let mut found = HashSet::new();
+    while found.len() < wanted.len() {
+        let Some(header) = read_local_header(&mut reader)? else {
+            break;
+        };
+        let job = wanted
+            .iter()
+            .find(|(job, step)| is_step_log(&header.name, job, step))
+            .map(|&(job, _)| job);
+
+        let mut contents: Box<dyn Read + '_> = match header.method {
+            // Deflate streams mark their own end so this works even if the
+            // size is in a data descriptor after the data.
+            DEFLATED => Box::new(DeflateDecoder::new(&mut reader)),
+            STORED if header.flags & USES_DATA_DESCRIPTOR == 0 => {
+                Box::new((&mut reader).take(header.compressed_size))
+            }
+            // The data descriptor is read along with the data.
+            STORED => Box::new(StoredData::new(&mut reader, header.zip64)),
+            method => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "unsupported compression method {method} for {}",
+                        header.name
+                    ),
+                ));
+            }
+        };
+        if let Some(job) = job {
+            on_log(job, &mut contents)?;
+            found.insert(job.id);
+        }
+        // Skip to the end of the entry.
+        io::copy(&mut contents, &mut io::sink())?;
+        drop(contents);
+
+        if header.method == DEFLATED && header.flags & USES_DATA_DESCRIPTOR != 0 {
+            skip_data_descriptor(&mut reader, header.zip64)?;
+        }
+    }
+    Ok(found)
+}
+
+struct LocalHeader {
+    name: String,
+    flags: u16,
+    method: u16,
+    compressed_size: u64,
+    zip64: bool,
+}
+
+/// Returns `None` once the central directory is reached, or its end if the
+/// archive is empty.
+fn read_local_header<R: Read>(reader: &mut R) -> io::Result<Option<LocalHeader>> {
+    match read_u32(reader)? {
+        LOCAL_HEADER_SIGNATURE => {}
+        CENTRAL_HEADER_SIGNATURE
+        | END_OF_CENTRAL_DIRECTORY_SIGNATURE
+        | ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE => return Ok(None),
+        _ => return Err(invalid("expected a zip local file header")),
+    }
+    let mut fixed = [0; 26];
+    reader.read_exact(&mut fixed)?;
+    let u16_at = |i: usize| u16::from_le_bytes([fixed[i], fixed[i + 1]]);
+    let u32_at =
+        |i: usize| u32::from_le_bytes([fixed[i], fixed[i + 1], fixed[i + 2], fixed[i + 3]]);
+    let flags = u16_at(2);
+    let method = u16_at(4);
+    let mut compressed_size = u64::from(u32_at(14));
+    let uncompressed_size = u32_at(18);
+    let name_len = usize::from(u16_at(22));
+    let extra_len = usize::from(u16_at(24));
+
+    let mut name = vec![0; name_len];
+    reader.read_exact(&mut name)?;
+    let mut extra = vec![0; extra_len];
+    reader.read_exact(&mut extra)?;
+
+    // The zip64 extra field holds the real sizes when they don't fit in 32 bits.
+    let mut zip64 = false;
+    let mut fields = &extra[..];
+    while let [a, b, c, d, rest @ ..] = fields {
+        let id = u16::from_le_bytes([*a, *b]);
+        let len = usize::from(u16::from_le_bytes([*c, *d])).min(rest.len());
+        let (data, rest) = rest.split_at(len);
+        if id == ZIP64_EXTRA {
+            zip64 = true;
+            // The uncompressed size comes first, if present.
+            let offset = if uncompressed_size == u32::MAX { 8 } else { 0 };
+            if compressed_size == u64::from(u32::MAX)
+                && let Some(size) = data.get(offset..offset + 8)
+            {
+                compressed_size = u64::from_le_bytes(size.try_into().unwrap());
+            }
+        }
+        fields = rest;
+    }
+
+    Ok(Some(LocalHeader {
+        name: String::from_utf8_lossy(&name).into_owned(),
+        flags,
+        method,
+        compressed_size,
+        zip64,
+    }))
+}
+
+fn skip_data_descriptor<R: Read>(reader: &mut R, zip64: bool) -> io::Result<()> {
+    // The signature is optional. If it's not there then we've just read the crc.
+    if read_u32(reader)? == DATA_DESCRIPTOR_SIGNATURE {
+        read_u32(reader)?;
+    }
+    let sizes_len = if zip64 { 16 } else { 8 };
+    io::copy(&mut reader.take(sizes_len), &mut io::sink())?;
+    Ok(())
+}
+
+/// The data of a stored entry whose size is only given in the data descriptor
+/// after it. Nothing marks where the data ends, so it's taken to be at the
+/// first descriptor that matches the data before it. That descriptor is read
+/// too.
+struct StoredData<'a, R> {
+    reader: &'a mut R,
+    zip64: bool,
+    /// What's been read but not returned, as it may be the descriptor.
+    pending: Vec<u8>,
+    /// Of what's been returned.
+    crc: Crc,
+    len: u64,
+    found: bool,
+}
+
+impl<'a, R: BufRead> StoredData<'a, R> {
+    fn new(reader: &'a mut R, zip64: bool) -> Self {
+        Self {
+            reader,
+            zip64,
+            pending: Vec::new(),
+            crc: Crc::new(),
+            len: 0,
+            found: false,
+        }
+    }
+
+    /// The length of a descriptor without its optional signature.
+    fn descriptor_len(&self) -> usize {
+        if self.zip64 { 20 } else { 12 }
+    }
+
+    /// Whether the last `len` bytes of what's pending are a descriptor for
+    /// everything before them.
+    fn ends_with_descriptor(&self, len: usize) -> bool {
+        let Some(start) = self.pending.len().checked_sub(len) else {
+            return false;
+        };
+        let (data, mut descriptor) = self.pending.split_at(start);
+        if len > self.descriptor_len() {
+            let Some(rest) = descriptor.strip_prefix(&DATA_DESCRIPTOR_SIGNATURE.to_le_bytes())
+            else {
+                return false;
+            };
+            descriptor = rest;
+        }
+        let (crc, sizes) = descriptor.split_at(4);
+        let (compressed, uncompressed) = sizes.split_at(sizes.len() / 2);
+        let size = |bytes: &[u8]| {
+            let mut size = [0; 8];
+            size[..bytes.len()].copy_from_slice(bytes);
+            u64::from_le_bytes(size)
+        };
+        let len = self.len + data.len() as u64;
+        if size(compressed) != len || size(uncompressed) != len {
+            return false;
+        }
+        let mut expected = Crc::new();
+        expected.combine(&self.crc);
+        expected.update(data);
+        expected.sum().to_le_bytes() == crc
+    }
+}
+
+impl<R: BufRead> Read for StoredData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() && !self.found {
+            let Some(&byte) = self.reader.fill_buf()?.first() else {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            };
+            self.reader.consume(1);
+            self.pending.push(byte);
+            let len = self.descriptor_len();
+            if let Some(len) = [len + 4, len]
+                .into_iter()
+                .find(|&len| self.ends_with_descriptor(len))
+            {
+                self.pending.truncate(self.pending.len() - len);
+                self.found = true;
+            } else if self.pending.len() > len + 4 {
+                let byte = self.pending.remove(0);
+                self.crc.update(&[byte]);
+                self.len += 1;
+                buf[n] = byte;
+                n += 1;
+            }
+        }
+        if self.found {
+            let count = (buf.len() - n).min(self.pending.len());
+            buf[n..n + count].copy_from_slice(&self.pending[..count]);
+            self.pending.drain(..count);
+            n += count;
+        }
+        Ok(n)
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Copies everything read into a writer, e.g. to cache a download while it's
+/// being read.
+struct Tee<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+/// Downloads a run's logs archive, reading the wanted step logs as it goes.
+/// See [`read_step_logs`].
+///
+/// If `keep` is given then the whole archive is also written to that path in
+/// the cache. Otherwise the download is stopped as soon as every wanted log
+/// has been found.
+pub fn download_step_logs(
+    run_id: u64,
+    wanted: &[(&Job, &Step)],
+    keep: Option<&Path>,
+    on_log: impl FnMut(&Job, &mut dyn Read) -> io::Result<()>,
+) -> Result<HashSet<u64>, GhError> {
+    let mut stream = GithubApi::new(&format!(
+        "repos/rust-lang-ci/rust/actions/runs/{run_id}/logs"
+    ))
+    .stream()?;
+    let Some(keep) = keep else {
+        return match read_step_logs(BufReader::new(&mut stream), wanted, on_log) {
+            // Dropping the stream stops the download.
+            Ok(logs) => Ok(logs),
+            Err(e) => {
+                // If gh failed then its error is more useful than ours.
+                let _ = io::copy(&mut stream, &mut io::sink());
+                stream.finish()?;
+                Err(GhError::Io(e))
+            }
+        };
+    };
+
+    let writer = cache::Writer::new(keep).map_err(GhError::Io)?;
+    let mut tee = BufReader::new(Tee {
+        reader: stream,
+        writer,
+    });
+    let logs = read_step_logs(&mut tee, wanted, on_log).and_then(|logs| {
+        // Read the rest so the whole archive is cached.
+        io::copy(&mut tee, &mut io::sink())?;
+        Ok(logs)
+    });
+    let Tee {
+        reader: mut stream,
+        writer,
+    } = tee.into_inner();
+    if logs.is_err() {
+        let _ = io::copy(&mut stream, &mut io::sink());
+    }
+    stream.finish()?;
+    let logs = logs.map_err(GhError::Io)?;
+    writer.finish().map_err(GhError::Io)?;
+    Ok(logs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::Conclusion;
+    use std::io::{Cursor, Seek};
+    use zip::CompressionMethod;
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    /// Looks like a data descriptor, to catch stored data being cut short.
+    const LOG: &[u8] =
+        b"error: real\nPK\x07\x08\x00\x00\x00\x00\x05\x00\x00\x00\x05\x00\x00\x00\nthe end\n";
+
+    fn job() -> Job {
+        Job {
+            id: 11,
+            html_url: String::new(),
+            conclusion: Conclusion::Failure,
+            started_at: String::new(),
+            name: "x86_64-gnu".into(),
+            steps: vec![Step {
+                name: "Run build".into(),
+                conclusion: Conclusion::Failure,
+                number: 4,
+                started_at: None,
+            }],
+        }
+    }
+
+    /// An archive with the log of step 4 of [`job`] after another file, as
+    /// GitHub makes them.
+    fn archive(method: CompressionMethod, stream: bool, zip64: bool) -> Vec<u8> {
+        let options = SimpleFileOptions::default()
+            .compression_method(method)
+            .large_file(zip64);
+        if stream {
+            let mut zip = ZipWriter::new_stream(Vec::new());
+            add_files(&mut zip, options);
+            zip.finish().unwrap().into_inner()
+        } else {
+            let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+            add_files(&mut zip, options);
+            zip.finish().unwrap().into_inner()
+        }
+    }
+
+    fn add_files<W: Write + Seek>(zip: &mut ZipWriter<W>, options: SimpleFileOptions) {
+        zip.start_file("x86_64-gnu/3_Checkout.txt", options)
+            .unwrap();
+        zip.write_all(b"checking out\n").unwrap();
+        zip.start_file("x86_64-gnu/4_Run build.txt", options)
+            .unwrap();
+        zip.write_all(LOG).unwrap();
+    }
+
+    fn read(archive: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let job = job();
+        let mut logs = Vec::new();
+        let found = read_step_logs(archive, &[(&job, &job.steps[0])], |job, log| {
+            assert_eq!(job.id, 11);
+            let mut contents = Vec::new();
+            log.read_to_end(&mut contents)?;
+            logs.push(contents);
+            Ok(())
+        })?;
+        assert_eq!(found.len(), logs.len());
+        Ok(logs)
+    }
+
+    #[test]
+    fn every_kind_of_entry() {
+        for method in [CompressionMethod::Deflated, CompressionMethod::Stored] {
+            for stream in [false, true] {
+                for zip64 in [false, true] {
+                    let logs = read(&archive(method, stream, zip64))
+                        .unwrap_or_else(|e| panic!("{method:?}, {stream}, {zip64}: {e}"));
+                    assert_eq!(logs, [LOG], "{method:?}, {stream}, {zip64}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_archive() {
+        let zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let archive = zip.finish().unwrap().into_inner();
+        assert!(read(&archive).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+//! Interpreting SGR (Select Graphic Rendition) escape sequences.
+//!
+//! rustc, cargo and libtest colour their output with `ESC [ ... m` sequences.
+//! Rather than throwing these away, the log keeps track of the style of each
+//! bit of text so it can be shown in colour.
+
+use crate::strip_ansi::AnsiMode;
+use std::fmt::{self, Write};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Colour {
+    /// One of the 256 colours of the xterm palette. The first 16 are the
+    /// standard and bright colours, which terminals are free to pick.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Style {
+    pub fg: Option<Colour>,
+    pub bg: Option<Colour>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Applies the parameters of an SGR sequence, e.g. `1;31`.
+    ///
+    /// Unsupported parameters are ignored.
+    pub fn apply(&mut self, params: &[u8]) {
+        // Some programs use `:` to separate the parts of extended colours.
+        let mut params = params
+            .split(|&b| b == b';' || b == b':')
+            .map(|p| std::str::from_utf8(p).ok()?.parse::<u8>().ok());
+        while let Some(param) = params.next() {
+            match param {
+                // An empty parameter means the same as 0.
+                None | Some(0) => *self = Self::default(),
+                Some(1) => self.bold = true,
+                Some(3) => self.italic = true,
+                Some(4) => self.underline = true,
+                Some(22) => self.bold = false,
+                Some(23) => self.italic = false,
+                Some(24) => self.underline = false,
+                Some(n @ 30..=37) => self.fg = Some(Colour::Indexed(n - 30)),
+                Some(38) => self.fg = extended_colour(&mut params),
+                Some(39) => self.fg = None,
+                Some(n @ 40..=47) => self.bg = Some(Colour::Indexed(n - 40)),
+                Some(48) => self.bg = extended_colour(&mut params),
+                Some(49) => self.bg = None,
+                Some(n @ 90..=97) => self.fg = Some(Colour::Indexed(n - 90 + 8)),
+                Some(n @ 100..=107) => self.bg = Some(Colour::Indexed(n - 100 + 8)),
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// The CSS for the style, for use in a `style` attribute.
+    pub fn css(&self) -> String {
+        let mut css = String::new();
+        if let Some(fg) = self.fg {
+            let _ = write!(css, "color:{};", fg.css());
+        }
+        if let Some(bg) = self.bg {
+            let _ = write!(css, "background-color:{};", bg.css());
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        if self.underline {
+            css.push_str("text-decoration:underline;");
+        }
+        css
+    }
+}
+
+/// The rest of a `38` or `48` parameter: either `5;n` or `2;r;g;b`.
+fn extended_colour(params: &mut impl Iterator<Item = Option<u8>>) -> Option<Colour> {
+    match params.next()?? {
+        5 => Some(Colour::Indexed(params.next()??)),
+        2 => Some(Colour::Rgb(
+            params.next()??,
+            params.next()??,
+            params.next()??,
+        )),
+        _ => None,
+    }
+}
+
+/// Writes the style as the SGR sequence that would select it.
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\x1b[0")?;
+        if self.bold {
+            f.write_str(";1")?;
+        }
+        if self.italic {
+            f.write_str(";3")?;
+        }
+        if self.underline {
+            f.write_str(";4")?;
+        }
+        for (colour, base) in [(self.fg, 38), (self.bg, 48)] {
+            match colour {
+                Some(Colour::Indexed(n)) => write!(f, ";{base};5;{n}")?,
+                Some(Colour::Rgb(r, g, b)) => write!(f, ";{base};2;{r};{g};{b}")?,
+                None => {}
+            }
+        }
+        f.write_str("m")
+    }
+}
+
+impl Colour {
+    /// The colour as a CSS hex colour.
+    fn css(self) -> String {
+        // Roughly the colours of the default xterm theme, a little darker so
+        // they can be read on a light background.
+        const BASIC: [(u8, u8, u8); 16] = [
+            (0x00, 0x00, 0x00),
+            (0xcd, 0x00, 0x00),
+            (0x00, 0x9a, 0x00),
+            (0xa6, 0x80, 0x00),
+            (0x00, 0x00, 0xee),
+            (0xcd, 0x00, 0xcd),
+            (0x00, 0x9a, 0x9a),
+            (0x7f, 0x7f, 0x7f),
+            (0x4d, 0x4d, 0x4d),
+            (0xff, 0x00, 0x00),
+            (0x00, 0xb8, 0x00),
+            (0xb8, 0x9a, 0x00),
+            (0x5c, 0x5c, 0xff),
+            (0xff, 0x00, 0xff),
+            (0x00, 0xb8, 0xb8),
+            (0x5a, 0x5a, 0x5a),
+        ];
+        let (r, g, b) = match self {
+            Self::Indexed(n @ 0..16) => BASIC[usize::from(n)],
+            // A 6x6x6 colour cube.
+            Self::Indexed(n @ 16..232) => {
+                let level = |i: u8| if i == 0 { 0 } else { 55 + i * 40 };
+                let n = n - 16;
+                (level(n / 36), level(n / 6 % 6), level(n % 6))
+            }
+            // Then shades of grey.
+            Self::Indexed(n) => {
+                let grey = 8 + (n - 232) * 10;
+                (grey, grey, grey)
+            }
+            Self::Rgb(r, g, b) => (r, g, b),
+        };
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+/// Strips escape sequences from text while keeping track of the style.
+///
+/// The state is kept between calls as styles can span lines. Sequences can't,
+/// see [`end_line`](Self::end_line).
+#[derive(Clone)]
+pub struct Interpreter {
+    mode: AnsiMode,
+    /// The parameters of the CSI sequence being read.
+    params: Vec<u8>,
+    pub style: Style,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self {
+            mode: AnsiMode::Text,
+            params: Vec::new(),
+            style: Style::default(),
+        }
+    }
+}
+
+impl Interpreter {
+    /// Feeds the next byte. Returns whether it's text, in which case it
+    /// should be shown in the current [`style`](Self::style).
+    pub fn update(&mut self, b: u8) -> bool {
+        let previous = self.mode;
+        match (previous, self.mode.update(b)) {
+            (AnsiMode::Parameter, AnsiMode::Parameter) => self.params.push(b),
+            // Either `ESC [` or the 8-bit CSI.
+            (_, AnsiMode::Parameter) => self.params.clear(),
+            (AnsiMode::Parameter, AnsiMode::Final) if b == b'm' => self.style.apply(&self.params),
+            _ => {}
+        }
+        self.mode.is_text()
+    }
+
+    /// Ends whatever sequence is part way through at the end of a line.
+    ///
+    /// Control strings only end with a terminator, so a stray introducer,
+    /// e.g. a 0x9D byte in a binary dump, would otherwise hide the rest of
+    /// the log.
+    pub fn end_line(&mut self) {
+        self.mode = AnsiMode::Text;
+    }
+}
@@ -0,0 +1,119 @@
+//! Splitting a job's log into its steps.
+//!
+//! The log downloaded for a job is every step concatenated together with
+//! nothing explicitly marking where one step ends and the next begins. But
+//! every line is timestamped and the API tells us when each step started, so
+//! lines can be assigned to steps by time.
+//!
+//! Step start times only have a resolution of seconds. Within the second a
+//! step started, the boundary is taken to be the first `##[group]` line, which
+//! is how each step's output begins.
+
+use crate::github::Step;
+use crate::log::{Line, LineKind};
+use jiff::{SignedDuration, Timestamp};
+
+/// Assigns lines to steps as they're read from the log.
+///
+/// Lines before the first step, or in a log without timestamps, are left
+/// without a step.
+pub struct Assigner {
+    starts: Vec<(u64, Timestamp)>,
+    /// The index into `starts` of the step the current line belongs to.
+    current: Option<usize>,
+}
+
+impl Assigner {
+    pub fn new(steps: &[Step]) -> Self {
+        Self {
+            starts: steps
+                .iter()
+                .filter_map(|s| Some((s.number, s.started_at.as_deref()?.parse().ok()?)))
+                .collect(),
+            current: None,
+        }
+    }
+
+    /// Sets the step of the next line in the log.
+    pub fn assign(&mut self, line: &mut Line) {
+        if let Some(time) = line.time {
+            let is_group = line.kind == LineKind::GroupStart;
+            loop {
+                let next = self.current.map_or(0, |c| c + 1);
+                let Some(&(_, start)) = self.starts.get(next) else {
+                    break;
+                };
+                if time >= start + SignedDuration::from_secs(1) {
+                    self.current = Some(next);
+                    continue;
+                }
+                // Steps can start in the same second, so a group only starts
+                // one of them.
+                if time >= start && is_group {
+                    self.current = Some(next);
+                }
+                break;
+            }
+        }
+        line.step = self.current.map(|c| self.starts[c].0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::Conclusion;
+    use crate::log::Log;
+
+    fn step(number: u64, started_at: &str) -> Step {
+        Step {
+            name: format!("step {number}"),
+            conclusion: Conclusion::Success,
+            number,
+            started_at: Some(started_at.into()),
+        }
+    }
+
+    fn assign(steps: &[Step], log: &str) -> Vec<Option<u64>> {
+        let mut assigner = Assigner::new(steps);
+        Log::parse(log)
+            .lines
+            .into_iter()
+            .map(|mut line| {
+                assigner.assign(&mut line);
+                line.step
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steps_in_the_same_second() {
+        let steps = [
+            step(1, "2025-04-01T00:00:00Z"),
+            step(2, "2025-04-01T00:00:05Z"),
+            step(3, "2025-04-01T00:00:05Z"),
+            step(4, "2025-04-01T00:00:07Z"),
+        ];
+        let log = "\
+2025-04-01T00:00:00.1Z ##[group]One
+2025-04-01T00:00:05.0Z still one
+2025-04-01T00:00:05.1Z ##[group]Two
+2025-04-01T00:00:05.2Z two
+2025-04-01T00:00:05.3Z ##[group]Three
+2025-04-01T00:00:06.0Z three
+2025-04-01T00:00:07.5Z ##[group]Four
+";
+        assert_eq!(
+            assign(&steps, log),
+            [
+                Some(1),
+                Some(1),
+                Some(2),
+                Some(2),
+                Some(3),
+                Some(3),
+                Some(4)
+            ]
+        );
+    }
+}
@@ -1,22 +1,48 @@
 // Taken from my reading of ECMA-35 and ECMA-48
 
+use std::borrow::Cow;
+
 const BEL: u8 = 0x7;
 const ESC: u8 = 0x1B;
 const CSI: u8 = b'[';
 const OSC: u8 = b']';
+const DCS: u8 = b'P';
+const SOS: u8 = b'X';
+const PM: u8 = b'^';
+const APC: u8 = b'_';
+const ST: u8 = b'\\';
+
+// The 8-bit forms of the C1 controls, which are ESC followed by the byte
+// with 0x40 subtracted.
+const C1_DCS: u8 = 0x90;
+const C1_SOS: u8 = 0x98;
+const C1_CSI: u8 = 0x9B;
+const C1_ST: u8 = 0x9C;
+const C1_OSC: u8 = 0x9D;
+const C1_PM: u8 = 0x9E;
+const C1_APC: u8 = 0x9F;
 
 // Quick state machine.
 // This could be optimized.
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum AnsiMode {
     Text,
+    /// Part way through a UTF-8 encoded character, with this many bytes to go.
+    ///
+    /// The continuation bytes of UTF-8 overlap the 8-bit C1 controls so they
+    /// can only be told apart by what came before.
+    Utf8(u8),
     Escape,
     Osc,
     /// `OscEscape` on its own is ambiguous.
-    /// If it's followed by OscTerminator then it ends the Osc text.
+    /// If it's followed by StringTerminator then it ends the Osc text.
     /// If not then it's part of the Osc text.
     OscEscape,
-    OscTerminator,
+    /// A DCS, SOS, PM or APC string. These are like Osc but only end with ST.
+    ControlString,
+    /// Like `OscEscape`.
+    ControlStringEscape,
+    StringTerminator,
     Parameter,
     Intermidiate,
     Final,
@@ -29,29 +55,194 @@ impl AnsiMode {
 
     pub fn next(self, b: u8) -> Self {
         match (self, b) {
-            (Self::Text, ESC) => Self::Escape,
+            (Self::Utf8(n), 0x80..=0xbf) => {
+                if n > 1 {
+                    Self::Utf8(n - 1)
+                } else {
+                    Self::Text
+                }
+            }
+            // A broken character. Start again from this byte.
+            (Self::Utf8(_), _) => Self::Text.next(b),
+            (Self::Text | Self::Final | Self::StringTerminator, _) => Self::start(b),
+            // An escape part way through a sequence starts a new one.
+            (Self::Escape | Self::Parameter | Self::Intermidiate, ESC) => Self::Escape,
             (Self::Escape, CSI) => Self::Parameter,
             (Self::Escape, OSC) => Self::Osc,
+            (Self::Escape, DCS | SOS | PM | APC) => Self::ControlString,
             (Self::Escape, 0x20..=0x2f) => Self::Intermidiate,
-            (Self::Escape, 0x40..=0x7e) => Self::Final,
+            (Self::Escape, 0x30..=0x7e) => Self::Final,
             (Self::Parameter, 0x30..=0x3f) => Self::Parameter,
             (Self::Parameter, 0x20..=0x2f) => Self::Intermidiate,
             (Self::Parameter, 0x40..=0x7e) => Self::Final,
             (Self::Intermidiate, 0x20..=0x2f) => Self::Intermidiate,
-            (Self::Intermidiate, 0x40..=0x7e) => Self::Final,
+            (Self::Intermidiate, 0x30..=0x7e) => Self::Final,
             // Handle Operating System Commands
             (Self::Osc, ESC) => Self::OscEscape,
-            (Self::OscEscape, b'\\') => Self::OscTerminator,
+            (Self::OscEscape, ST) => Self::StringTerminator,
             (Self::OscEscape, ESC) => Self::OscEscape,
-            (Self::Osc | Self::OscEscape, BEL) => Self::OscTerminator,
+            (Self::Osc | Self::OscEscape, BEL | C1_ST) => Self::StringTerminator,
             (Self::Osc | Self::OscEscape, _) => Self::Osc,
-            (Self::Final | Self::OscTerminator, ESC) => Self::Escape,
+            // And the other control strings
+            (Self::ControlString, ESC) => Self::ControlStringEscape,
+            (Self::ControlStringEscape, ST) => Self::StringTerminator,
+            (Self::ControlStringEscape, ESC) => Self::ControlStringEscape,
+            (Self::ControlString | Self::ControlStringEscape, C1_ST) => Self::StringTerminator,
+            (Self::ControlString | Self::ControlStringEscape, _) => Self::ControlString,
             // Anything else is just text
             _ => Self::Text,
         }
     }
 
+    /// The state after `b` when not in the middle of anything.
+    fn start(b: u8) -> Self {
+        match b {
+            ESC => Self::Escape,
+            C1_CSI => Self::Parameter,
+            C1_OSC => Self::Osc,
+            C1_DCS | C1_SOS | C1_PM | C1_APC => Self::ControlString,
+            // Any other C1 control is complete on its own.
+            0x80..=0x9f => Self::Final,
+            0xc2..=0xdf => Self::Utf8(1),
+            0xe0..=0xef => Self::Utf8(2),
+            0xf0..=0xf4 => Self::Utf8(3),
+            _ => Self::Text,
+        }
+    }
+
     pub fn is_text(self) -> bool {
-        self == Self::Text
+        matches!(self, Self::Text | Self::Utf8(_))
+    }
+}
+
+/// Removes ANSI escape sequences and other control functions from text.
+///
+/// Carriage returns are left alone. What they overwrite depends on how the
+/// text is split into lines.
+pub fn strip_ansi(input: &[u8]) -> Cow<'_, [u8]> {
+    let mut mode = AnsiMode::Text;
+    let Some(first) = input.iter().position(|&b| !mode.update(b).is_text()) else {
+        return Cow::Borrowed(input);
+    };
+    let mut stripped = input[..first].to_vec();
+    stripped.extend(
+        input[first + 1..]
+            .iter()
+            .filter(|&&b| mode.update(b).is_text()),
+    );
+    Cow::Owned(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Any text without escapes. Encoded as UTF-8, C1 control characters are
+    /// ordinary text too.
+    fn ordinary_text() -> impl Strategy<Value = String> {
+        any::<String>().prop_map(|s| s.replace('\x1b', ""))
+    }
+
+    /// A complete escape sequence or control string, in any of its forms.
+    fn sequence() -> impl Strategy<Value = Vec<u8>> {
+        let params = "[0-9;:<=>?]{0,12}";
+        let string = proptest::collection::vec(
+            any::<u8>().prop_filter("not a terminator", |&b| ![ESC, BEL, C1_ST].contains(&b)),
+            0..20,
+        );
+        let introducer = prop_oneof![
+            Just(vec![ESC, DCS]),
+            Just(vec![ESC, SOS]),
+            Just(vec![ESC, PM]),
+            Just(vec![ESC, APC]),
+            Just(vec![C1_DCS]),
+            Just(vec![C1_SOS]),
+            Just(vec![C1_PM]),
+            Just(vec![C1_APC]),
+        ];
+        prop_oneof![
+            // CSI
+            (
+                prop_oneof![Just(vec![ESC, CSI]), Just(vec![C1_CSI])],
+                params,
+                "[ -/]{0,2}",
+                0x40..=0x7eu8
+            )
+                .prop_map(|(mut seq, params, intermediates, end)| {
+                    seq.extend(params.bytes().chain(intermediates.bytes()));
+                    seq.push(end);
+                    seq
+                }),
+            // OSC
+            (
+                prop_oneof![Just(vec![ESC, OSC]), Just(vec![C1_OSC])],
+                string.clone(),
+                prop_oneof![Just(vec![BEL]), Just(vec![ESC, ST]), Just(vec![C1_ST])],
+            )
+                .prop_map(|(mut seq, string, end)| {
+                    seq.extend(string);
+                    seq.extend(end);
+                    seq
+                }),
+            // DCS, SOS, PM and APC
+            (
+                introducer,
+                string,
+                prop_oneof![Just(vec![ESC, ST]), Just(vec![C1_ST])],
+            )
+                .prop_map(|(mut seq, string, end)| {
+                    seq.extend(string);
+                    seq.extend(end);
+                    seq
+                }),
+            // Other escapes, e.g. `ESC ( B` or `ESC 7`
+            ("[ -/]{0,2}", 0x30..=0x7eu8)
+                .prop_filter("not an introducer", |(i, end)| {
+                    !i.is_empty() || ![CSI, OSC, DCS, SOS, PM, APC].contains(end)
+                })
+                .prop_map(|(intermediates, end)| {
+                    let mut seq = vec![ESC];
+                    seq.extend(intermediates.bytes());
+                    seq.push(end);
+                    seq
+                }),
+            // Other C1 controls
+            (0x80..=0x9fu8)
+                .prop_filter("not an introducer", |b| {
+                    ![C1_CSI, C1_OSC, C1_DCS, C1_SOS, C1_PM, C1_APC].contains(b)
+                })
+                .prop_map(|b| vec![b]),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn never_eats_ordinary_text(text in ordinary_text()) {
+            let stripped = strip_ansi(text.as_bytes());
+            prop_assert!(matches!(stripped, Cow::Borrowed(_)));
+            prop_assert_eq!(&*stripped, text.as_bytes());
+        }
+
+        #[test]
+        fn removes_only_sequences(parts in proptest::collection::vec((ordinary_text(), sequence()), 0..8)) {
+            let mut input = Vec::new();
+            let mut text = Vec::new();
+            for (part, seq) in &parts {
+                input.extend_from_slice(part.as_bytes());
+                input.extend_from_slice(seq);
+                text.extend_from_slice(part.as_bytes());
+            }
+            prop_assert_eq!(&*strip_ansi(&input), &text[..]);
+        }
+
+        #[test]
+        fn output_is_a_subsequence(input in any::<Vec<u8>>()) {
+            let stripped = strip_ansi(&input);
+            let mut rest = input.iter();
+            for b in stripped.iter() {
+                prop_assert!(rest.any(|i| i == b));
+            }
+        }
     }
 }
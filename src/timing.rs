@@ -0,0 +1,128 @@
+//! Working out when things happened from the log's timestamps.
+
+use crate::log::{Line, LineKind, Log};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+/// GitHub cancels jobs that run for longer than this.
+///
+/// This is GitHub's default, and the most a job on one of its hosted runners
+/// can have. It's assumed to be every job's limit, as a job's own
+/// `timeout-minutes` isn't in the API's job details.
+const JOB_TIME_LIMIT: u64 = 6 * 60 * 60;
+/// How close to the time limit a failure has to be to count as near it.
+const NEAR_TIME_LIMIT: u64 = 15 * 60;
+
+/// How long a group in the log took.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GroupTiming {
+    pub name: String,
+    /// When the group started.
+    pub start: String,
+    /// In seconds.
+    pub duration: u64,
+}
+
+/// When a job failed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Timing {
+    /// Seconds from the start of the job to the failure.
+    pub failed_after: u64,
+    /// The group the failure happened in, if any.
+    pub group: Option<String>,
+    /// Seconds from the start of that group to the failure.
+    pub group_duration: Option<u64>,
+    /// Whether the job was close to being cancelled for running too long.
+    pub near_time_limit: bool,
+}
+
+/// Times each group in the log, including nested groups, as it's read.
+///
+/// Groups without timestamps are skipped.
+#[derive(Default)]
+pub struct GroupTimer {
+    /// The groups that haven't ended yet, with their first and last times.
+    open: Vec<(String, Option<(Timestamp, Timestamp)>)>,
+    done: Vec<(Timestamp, GroupTiming)>,
+}
+
+impl GroupTimer {
+    pub fn push(&mut self, line: &Line) {
+        if line.kind == LineKind::GroupStart {
+            self.open.push((line.message().into(), None));
+        }
+        if let Some(time) = line.time {
+            for (_, times) in &mut self.open {
+                times.get_or_insert((time, time)).1 = time;
+            }
+        }
+        // The end line is part of the group so it's closed after its time is used.
+        if line.kind == LineKind::GroupEnd {
+            self.close();
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some((name, Some((start, end)))) = self.open.pop() {
+            self.done.push((
+                start,
+                GroupTiming {
+                    name,
+                    start: start.to_string(),
+                    duration: seconds_between(start, end),
+                },
+            ));
+        }
+    }
+
+    /// The timings in the order the groups started. A group that's never
+    /// ended runs until the end of the log.
+    pub fn finish(mut self) -> Vec<GroupTiming> {
+        while !self.open.is_empty() {
+            self.close();
+        }
+        self.done.sort_by_key(|&(start, _)| start);
+        self.done.into_iter().map(|(_, timing)| timing).collect()
+    }
+}
+
+/// Works out when a job failed.
+///
+/// `log` is the trimmed log, which starts with the group that failed, and
+/// `failure` is the line that best describes the failure. If that doesn't have
+/// a timestamp then the end of the log is used instead.
+pub fn timing(job_started: Timestamp, log: &Log, failure: Option<&Line>) -> Option<Timing> {
+    let failed_at = failure
+        .and_then(|line| line.time)
+        .or_else(|| last_time(&log.lines))?;
+    let group = log.lines.first().filter(|l| l.kind == LineKind::GroupStart);
+    let failed_after = seconds_between(job_started, failed_at);
+    Some(Timing {
+        failed_after,
+        group: group.map(|g| g.message().into()),
+        group_duration: group
+            .and_then(|g| g.time)
+            .map(|start| seconds_between(start, failed_at)),
+        near_time_limit: failed_after + NEAR_TIME_LIMIT >= JOB_TIME_LIMIT,
+    })
+}
+
+fn last_time(lines: &[Line]) -> Option<Timestamp> {
+    lines.iter().rev().find_map(|l| l.time)
+}
+
+fn seconds_between(start: Timestamp, end: Timestamp) -> u64 {
+    end.duration_since(start).as_secs().max(0) as u64
+}
+
+/// Formats a number of seconds like `2h13m`.
+pub fn format_duration(secs: u64) -> String {
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}h{minutes:02}m")
+    } else if minutes > 0 {
+        format!("{minutes}m{secs:02}s")
+    } else {
+        format!("{secs}s")
+    }
+}